@@ -0,0 +1,80 @@
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+use termchat::layout::MessageLines;
+
+const MESSAGES: usize = 100_000;
+const WIDTH: usize = 120;
+const HEIGHT: usize = 40;
+
+fn history() -> Vec<(String, String)> {
+    (0..MESSAGES)
+        .map(|i| {
+            let prefix = format!("[12:{:02}:{:02}] user{}: ", i / 60 % 60, i % 60, i % 7);
+            let words = 5 + i % 40;
+            let text = (0..words)
+                .map(|w| if w % 9 == 0 { "ünïcödé" } else { "word" })
+                .collect::<Vec<_>>()
+                .join(" ");
+            (prefix, text)
+        })
+        .collect()
+}
+
+fn laid_out(history: &[(String, String)]) -> MessageLines<()> {
+    let mut lines = MessageLines::new();
+    lines.set_width(WIDTH);
+    for (prefix, text) in history {
+        lines.push(vec![(prefix.clone(), ())], text.clone(), ());
+    }
+    lines
+}
+
+fn layout(c: &mut Criterion) {
+    let history = history();
+    let lines = laid_out(&history);
+
+    c.bench_function("lay out 100k messages", |b| {
+        b.iter(|| laid_out(black_box(&history)))
+    });
+    c.bench_function("resize 100k messages", |b| {
+        b.iter_batched(
+            || laid_out(&history),
+            |mut lines| {
+                lines.set_width(WIDTH / 2);
+                lines
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("visible window at the bottom", |b| {
+        let start = lines.row_count() - HEIGHT;
+        b.iter(|| black_box(&lines).rows(start, HEIGHT).count())
+    });
+    c.bench_function("visible window in the middle", |b| {
+        let start = lines.row_count() / 2;
+        b.iter(|| black_box(&lines).rows(start, HEIGHT).count())
+    });
+    c.bench_function("append one message", |b| {
+        b.iter_batched(
+            || laid_out(&history[..1000]),
+            |mut lines| {
+                lines.push(
+                    vec![("[12:00:00] bob: ".to_string(), ())],
+                    "hi".to_string(),
+                    (),
+                );
+                lines
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+// Laying out the whole history takes a good fraction of a second, so keep
+// the sample count low enough for `cargo bench` to finish quickly.
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = layout
+}
+criterion_main!(benches);
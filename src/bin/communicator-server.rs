@@ -0,0 +1,32 @@
+use std::{io, path::PathBuf};
+
+use clap::Parser;
+use termchat::{server::Server, store::MessageStore};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Chat server for the communicator client", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "0.0.0.0:9001")]
+    listen: String,
+
+    /// SQLite database to keep chat history in; nothing is kept without it
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Latest messages of a room sent to clients joining it
+    #[arg(long, default_value_t = 50)]
+    replay: usize,
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = Args::parse();
+
+    let mut server = Server::bind(&args.listen).await?;
+    if let Some(db) = &args.db {
+        server = server.with_store(MessageStore::open(db)?, args.replay);
+        println!("Keeping history in {}", db.display());
+    }
+    println!("WebSocket server started on ws://{}", server.local_addr()?);
+    server.run().await
+}
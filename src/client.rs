@@ -0,0 +1,420 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard, mpsc},
+    thread,
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    connection::{self, Command, ConnectOptions, ConnectionState, ConnectionStatus, Inbound},
+    outbox::{Outbox, OutboxEntry},
+    protocol::{ClientMessage, DEFAULT_ROOM, Incoming, PROTOCOL_VERSION, Presence, ServerEvent},
+};
+
+// Frames kept for a client nobody subscribes to, which is enough for the
+// greeting and the first history pages.
+const MAX_BACKLOG: usize = 256;
+
+#[derive(Default)]
+struct Subscribers {
+    senders: Vec<mpsc::Sender<Incoming>>,
+    // Frames that arrived before anyone subscribed, so the first subscriber
+    // still sees the server's greeting. Later ones are dropped once it is
+    // full.
+    backlog: Vec<Incoming>,
+}
+
+impl Subscribers {
+    fn publish(&mut self, incoming: Incoming) {
+        if self.senders.is_empty() {
+            if self.backlog.len() < MAX_BACKLOG {
+                self.backlog.push(incoming);
+            }
+        } else {
+            self.senders.retain(|tx| tx.send(incoming.clone()).is_ok());
+        }
+    }
+}
+
+// What the client knows about the server it talks to.
+#[derive(Default)]
+struct Session {
+    // Set once the server greets us with the structured protocol; legacy
+    // servers never do.
+    server_version: Option<u32>,
+    // The nick the server currently knows us by, which lags behind the
+    // requested one until the server confirms the change.
+    server_nick: Option<String>,
+    rooms: Vec<String>,
+    presence: Presence,
+}
+
+// Headless chat client: owns the reconnecting connection, the outbox and the
+// current nick. The TUI is one consumer; bots and tests can be others.
+pub struct ChatClient {
+    commands: UnboundedSender<Command>,
+    status: Arc<Mutex<ConnectionStatus>>,
+    outbox: Arc<Mutex<Outbox>>,
+    subscribers: Arc<Mutex<Subscribers>>,
+    session: Arc<Mutex<Session>>,
+    nick: Arc<Mutex<String>>,
+}
+
+impl ChatClient {
+    pub fn connect(options: ConnectOptions, nick: impl Into<String>, outbox: Outbox) -> Self {
+        let status = Arc::new(Mutex::new(ConnectionStatus::new(&options.urls[0])));
+        let outbox = Arc::new(Mutex::new(outbox));
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let session = Arc::new(Mutex::new(Session {
+            server_version: None,
+            server_nick: None,
+            rooms: vec![DEFAULT_ROOM.to_string()],
+            presence: Presence::Active,
+        }));
+        let nick = Arc::new(Mutex::new(nick.into()));
+
+        let encode_session = Arc::clone(&session);
+        let encode_nick = Arc::clone(&nick);
+        let encode_subscribers = Arc::clone(&subscribers);
+        let encode = Arc::new(move |entry: &OutboxEntry| {
+            let nick = encode_nick.lock().unwrap().clone();
+            let frame = encode(&encode_session.lock().unwrap(), &nick, entry);
+            if frame.is_none() {
+                let message = format!(
+                    "Dropped message to {}: this server does not support private messages",
+                    entry.room
+                );
+                encode_subscribers
+                    .lock()
+                    .unwrap()
+                    .publish(Incoming::Event(ServerEvent::Error { message }));
+            }
+            frame
+        });
+
+        let (frame_tx, frame_rx) = mpsc::channel::<Inbound>();
+        let commands = connection::spawn(
+            options,
+            frame_tx,
+            Arc::clone(&status),
+            Arc::clone(&outbox),
+            encode,
+        );
+
+        let dispatch = Arc::clone(&subscribers);
+        let dispatch_session = Arc::clone(&session);
+        // Only a weak handle, so dropping the client closes the command
+        // channel, which shuts the connection down and with it this thread.
+        let dispatch_commands = commands.downgrade();
+        let dispatch_nick = Arc::clone(&nick);
+        let dispatch_status = Arc::clone(&status);
+        thread::spawn(move || {
+            let resume = || {
+                if let Some(commands) = dispatch_commands.upgrade() {
+                    let _ = commands.send(Command::Resume);
+                }
+            };
+            for inbound in frame_rx {
+                let frame = match inbound {
+                    Inbound::Frame(frame) => frame,
+                    // Whoever answers next has to greet us again.
+                    Inbound::Closed => {
+                        let mut session = dispatch_session.lock().unwrap();
+                        session.server_version = None;
+                        session.server_nick = None;
+                        continue;
+                    }
+                };
+                let incoming = Incoming::parse(&frame);
+                // Legacy servers greet, if at all, in plain text and expect
+                // no answer.
+                if let Incoming::Legacy(_) = &incoming {
+                    resume();
+                }
+                if let Incoming::Event(event) = &incoming {
+                    let mut session = dispatch_session.lock().unwrap();
+                    match event {
+                        // Stay with the legacy protocol rather than send
+                        // frames the server may read differently.
+                        ServerEvent::Welcome { version, .. } if *version != PROTOCOL_VERSION => {
+                            dispatch_status.lock().unwrap().last_error = Some(format!(
+                                "Server speaks protocol version {}, not {}",
+                                version, PROTOCOL_VERSION
+                            ));
+                            resume();
+                        }
+                        // A fresh connection starts with no memberships and
+                        // an anonymous nick, so claim the nick and rejoin
+                        // every room the user had open.
+                        ServerEvent::Welcome { version, nick } => {
+                            session.server_version = Some(*version);
+                            session.server_nick = Some(nick.clone());
+                            let wanted = dispatch_nick.lock().unwrap().clone();
+                            let mut frames = Vec::new();
+                            if wanted != *nick {
+                                frames.push(ClientMessage::Nick { nick: wanted });
+                            }
+                            if session.presence != Presence::Active {
+                                frames.push(ClientMessage::Presence {
+                                    presence: session.presence,
+                                });
+                            }
+                            for room in session.rooms.iter().filter(|room| *room != DEFAULT_ROOM) {
+                                frames.push(ClientMessage::Join { room: room.clone() });
+                            }
+                            if let Some(commands) = dispatch_commands.upgrade() {
+                                for message in frames {
+                                    let _ = commands.send(Command::Send(message.to_frame()));
+                                }
+                            }
+                            // Queued messages follow once these are out.
+                            resume();
+                        }
+                        ServerEvent::NickChange {
+                            old_nick, new_nick, ..
+                        } if session.server_nick.as_ref() == Some(old_nick) => {
+                            session.server_nick = Some(new_nick.clone());
+                        }
+                        // Fall back to the nick the server still has for us.
+                        ServerEvent::NickRejected { nick, .. } => {
+                            let mut wanted = dispatch_nick.lock().unwrap();
+                            if *wanted == *nick
+                                && let Some(server_nick) = &session.server_nick
+                            {
+                                *wanted = server_nick.clone();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                dispatch.lock().unwrap().publish(incoming);
+            }
+        });
+
+        Self {
+            commands,
+            status,
+            outbox,
+            subscribers,
+            session,
+            nick,
+        }
+    }
+
+    // Returns a receiver for every frame received from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<Incoming> {
+        let (tx, rx) = mpsc::channel();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        for incoming in subscribers.backlog.drain(..) {
+            let _ = tx.send(incoming);
+        }
+        subscribers.senders.push(tx);
+        rx
+    }
+
+    pub fn nick(&self) -> String {
+        self.nick.lock().unwrap().clone()
+    }
+
+    // Asks the server for a new nick. If it is refused, a
+    // `ServerEvent::NickRejected` follows and `nick()` goes back to the old
+    // one. Legacy servers pick up the new nick with the next message.
+    pub fn set_nick(&self, nick: &str) {
+        *self.nick.lock().unwrap() = nick.to_string();
+        if self.supports_rooms() {
+            let message = ClientMessage::Nick {
+                nick: nick.to_string(),
+            };
+            self.notify(Command::Send(message.to_frame()));
+        }
+    }
+
+    // True once the server has announced the structured protocol, which is
+    // required for rooms and direct messages.
+    pub fn supports_rooms(&self) -> bool {
+        self.session.lock().unwrap().server_version.is_some()
+    }
+
+    // True while connected to a server that has not announced the structured
+    // protocol. Unlike `!supports_rooms()` this is false while offline, when
+    // the next server may well support rooms.
+    pub fn is_legacy(&self) -> bool {
+        !self.supports_rooms() && self.status().state == ConnectionState::Connected
+    }
+
+    pub fn rooms(&self) -> Vec<String> {
+        self.session.lock().unwrap().rooms.clone()
+    }
+
+    pub fn presence(&self) -> Presence {
+        self.session.lock().unwrap().presence
+    }
+
+    // Tells the server whether the user is active, idle or away. Legacy
+    // servers have no notion of presence.
+    pub fn set_presence(&self, presence: Presence) {
+        let mut session = self.session.lock().unwrap();
+        session.presence = presence;
+        if session.server_version.is_some() {
+            let message = ClientMessage::Presence { presence };
+            self.notify(Command::Send(message.to_frame()));
+        }
+    }
+
+    // Announces that the user started or stopped typing in `room`. Nothing
+    // is sent to legacy servers or while disconnected.
+    pub fn set_typing(&self, room: &str, typing: bool) {
+        if self.supports_rooms() {
+            let message = ClientMessage::Typing {
+                room: room.to_string(),
+                typing,
+            };
+            self.notify(Command::Send(message.to_frame()));
+        }
+    }
+
+    // Asks for up to `limit` stored messages of `room` older than message
+    // `before`; they arrive as `ServerEvent::History`. Nothing is sent to
+    // legacy servers.
+    pub fn load_older(&self, room: &str, before: Option<u64>, limit: usize) {
+        if self.supports_rooms() {
+            let message = ClientMessage::History {
+                room: room.to_string(),
+                before,
+                limit,
+            };
+            self.notify(Command::Send(message.to_frame()));
+        }
+    }
+
+    pub fn join(&self, room: &str) {
+        let mut session = self.session.lock().unwrap();
+        if !session.rooms.iter().any(|joined| joined == room) {
+            session.rooms.push(room.to_string());
+        }
+        let join = ClientMessage::Join {
+            room: room.to_string(),
+        };
+        self.notify(Command::Send(join.to_frame()));
+    }
+
+    pub fn part(&self, room: &str) {
+        self.session
+            .lock()
+            .unwrap()
+            .rooms
+            .retain(|joined| joined != room);
+        let part = ClientMessage::Part {
+            room: room.to_string(),
+        };
+        self.notify(Command::Send(part.to_frame()));
+    }
+
+    // Queues a chat message for the default room.
+    pub fn send(&self, text: &str) {
+        self.send_to(DEFAULT_ROOM, text);
+    }
+
+    // Queues a chat message. It is sent right away when connected, or as
+    // soon as the connection comes back otherwise.
+    pub fn send_to(&self, room: &str, text: &str) {
+        self.queue_chat(room, text, false);
+    }
+
+    // Queues a `/me` action for `room`.
+    pub fn send_action(&self, room: &str, text: &str) {
+        self.queue_chat(room, text, true);
+    }
+
+    // Queues a private message to `to`. Returns false without queueing when
+    // the server only speaks the legacy protocol, which would broadcast it;
+    // if that only turns out on reconnecting, the entry is dropped with a
+    // `ServerEvent::Error`. Pending entries are filed under the `@nick`
+    // conversation.
+    pub fn send_direct(&self, to: &str, text: &str) -> bool {
+        if self.is_legacy() {
+            return false;
+        }
+
+        self.outbox
+            .lock()
+            .unwrap()
+            .push(&format!("@{}", to), text.to_string(), false);
+        self.notify(Command::Flush);
+        true
+    }
+
+    pub fn reconnect(&self) {
+        self.notify(Command::Reconnect);
+    }
+
+    pub fn cancel_last_pending(&self) -> Option<OutboxEntry> {
+        self.outbox.lock().unwrap().cancel_last()
+    }
+
+    // Messages that have been sent with `send` but not yet written to the
+    // socket.
+    pub fn pending(&self) -> MutexGuard<'_, Outbox> {
+        self.outbox.lock().unwrap()
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn queue_chat(&self, room: &str, text: &str, action: bool) {
+        self.outbox
+            .lock()
+            .unwrap()
+            .push(room, text.to_string(), action);
+        self.notify(Command::Flush);
+    }
+
+    fn notify(&self, command: Command) {
+        if self.commands.send(command).is_err() {
+            let mut status = self.status.lock().unwrap();
+            status.state = ConnectionState::Disconnected;
+            status.last_error = Some("Connection worker stopped".to_string());
+        }
+    }
+}
+
+// Builds the frame for a queued entry in the protocol of the current server.
+// Returns `None` for direct messages to legacy servers, which would broadcast
+// them.
+fn encode(session: &Session, nick: &str, entry: &OutboxEntry) -> Option<String> {
+    if session.server_version.is_none() {
+        if entry.room.starts_with('@') {
+            return None;
+        }
+        // Legacy servers have no notion of actions.
+        let message = if entry.action {
+            format!("* {}", entry.text)
+        } else {
+            entry.text.clone()
+        };
+        return Some(serde_json::json!({ "nick": nick, "message": message }).to_string());
+    }
+
+    // Structured frames carry the nick the server has confirmed; a requested
+    // one may still be refused.
+    let nick = session
+        .server_nick
+        .clone()
+        .unwrap_or_else(|| nick.to_string());
+    let message = match entry.room.strip_prefix('@') {
+        Some(to) => ClientMessage::Direct {
+            nick,
+            to: to.to_string(),
+            text: entry.text.clone(),
+        },
+        None => ClientMessage::Chat {
+            nick,
+            room: entry.room.clone(),
+            text: entry.text.clone(),
+            action: entry.action,
+        },
+    };
+    Some(message.to_frame())
+}
@@ -0,0 +1,256 @@
+use crate::{export::ExportFormat, protocol::valid_room_name};
+
+// A slash command known to the input box. The table drives `/help` and Tab
+// completion; `parse` does the per-command argument checking.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "nick",
+        usage: "/nick <nick>",
+        help: "Change your nickname",
+    },
+    CommandSpec {
+        name: "join",
+        usage: "/join <room>",
+        help: "Join a room and switch to its tab",
+    },
+    CommandSpec {
+        name: "part",
+        usage: "/part",
+        help: "Leave the current room or close a direct conversation",
+    },
+    CommandSpec {
+        name: "msg",
+        usage: "/msg <nick> [text]",
+        help: "Send a private message",
+    },
+    CommandSpec {
+        name: "me",
+        usage: "/me <action>",
+        help: "Describe what you are doing",
+    },
+    CommandSpec {
+        name: "away",
+        usage: "/away",
+        help: "Mark yourself as away, or back again",
+    },
+    CommandSpec {
+        name: "clear",
+        usage: "/clear",
+        help: "Clear the messages of the current tab",
+    },
+    CommandSpec {
+        name: "export",
+        usage: "/export <md|html|json> [file]",
+        help: "Save the messages of the current tab to a file",
+    },
+    CommandSpec {
+        name: "quit",
+        usage: "/quit",
+        help: "Exit the client",
+    },
+    CommandSpec {
+        name: "help",
+        usage: "/help [command]",
+        help: "List commands or show how to use one",
+    },
+    CommandSpec {
+        name: "reconnect",
+        usage: "/reconnect",
+        help: "Reconnect now, skipping the backoff countdown",
+    },
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SlashCommand {
+    Nick(String),
+    Join(String),
+    Part,
+    Msg {
+        nick: String,
+        text: String,
+    },
+    Me(String),
+    Away,
+    Clear,
+    Export {
+        format: ExportFormat,
+        path: Option<String>,
+    },
+    Quit,
+    Help(Option<String>),
+    Reconnect,
+}
+
+// What the user submitted: either text to send as-is, or a command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Text(String),
+    Command(SlashCommand),
+}
+
+pub fn spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+// Parses a submitted message. Anything not starting with `/` is text, and a
+// leading `//` sends a literal `/`. Errors are meant to be shown to the user.
+pub fn parse(input: &str) -> Result<Input, String> {
+    let Some(body) = input.strip_prefix('/') else {
+        return Ok(Input::Text(input.to_string()));
+    };
+    if body.starts_with('/') {
+        return Ok(Input::Text(body.to_string()));
+    }
+
+    let (name, args) = match body.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (body, ""),
+    };
+    let Some(found) = spec(name) else {
+        return Err(format!("Unknown command '/{}' (try /help)", name));
+    };
+    let usage = || Err(format!("Usage: {}", found.usage));
+
+    let command = match name {
+        "nick" => {
+            if args.is_empty() || args.contains(char::is_whitespace) {
+                return usage();
+            }
+            SlashCommand::Nick(args.to_string())
+        }
+        "join" => {
+            let room = args.trim_start_matches('#');
+            if room.is_empty() {
+                return usage();
+            }
+            if !valid_room_name(room) {
+                return Err(format!("Invalid room name '{}'", room));
+            }
+            SlashCommand::Join(room.to_string())
+        }
+        "msg" => {
+            let (nick, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            if nick.is_empty() {
+                return usage();
+            }
+            SlashCommand::Msg {
+                nick: nick.to_string(),
+                text: text.trim_start().to_string(),
+            }
+        }
+        "me" => {
+            if args.is_empty() {
+                return usage();
+            }
+            SlashCommand::Me(args.to_string())
+        }
+        "export" => {
+            let (format, path) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            if format.is_empty() {
+                return usage();
+            }
+            let path = path.trim();
+            SlashCommand::Export {
+                format: format.parse()?,
+                path: (!path.is_empty()).then(|| path.to_string()),
+            }
+        }
+        "help" => match args {
+            "" => SlashCommand::Help(None),
+            command => {
+                let command = command.trim_start_matches('/');
+                if spec(command).is_none() {
+                    return Err(format!("Unknown command '/{}'", command));
+                }
+                SlashCommand::Help(Some(command.to_string()))
+            }
+        },
+        _ if !args.is_empty() => return usage(),
+        "part" => SlashCommand::Part,
+        "away" => SlashCommand::Away,
+        "clear" => SlashCommand::Clear,
+        "quit" => SlashCommand::Quit,
+        "reconnect" => SlashCommand::Reconnect,
+        _ => unreachable!("every command in COMMANDS is handled"),
+    };
+    Ok(Input::Command(command))
+}
+
+// Names of the commands starting with `prefix`, without the slash.
+pub fn complete(prefix: &str) -> Vec<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|spec| spec.name)
+        .filter(|name| name.starts_with(prefix))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_and_escaped_slashes_are_sent_as_is() {
+        assert_eq!(parse("hello"), Ok(Input::Text("hello".to_string())));
+        assert_eq!(
+            parse("//join is a command"),
+            Ok(Input::Text("/join is a command".to_string()))
+        );
+    }
+
+    #[test]
+    fn commands_are_parsed_with_their_arguments() {
+        assert_eq!(
+            parse("/join #rust"),
+            Ok(Input::Command(SlashCommand::Join("rust".to_string())))
+        );
+        assert_eq!(
+            parse("/msg bob hi\nthere"),
+            Ok(Input::Command(SlashCommand::Msg {
+                nick: "bob".to_string(),
+                text: "hi\nthere".to_string(),
+            }))
+        );
+        assert_eq!(parse("/quit"), Ok(Input::Command(SlashCommand::Quit)));
+        assert_eq!(
+            parse("/export html decisions.html"),
+            Ok(Input::Command(SlashCommand::Export {
+                format: ExportFormat::Html,
+                path: Some("decisions.html".to_string()),
+            }))
+        );
+        assert_eq!(
+            parse("/help /me"),
+            Ok(Input::Command(SlashCommand::Help(Some("me".to_string()))))
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        assert_eq!(parse("/nick"), Err("Usage: /nick <nick>".to_string()));
+        assert_eq!(parse("/nick a b"), Err("Usage: /nick <nick>".to_string()));
+        assert_eq!(parse("/quit now"), Err("Usage: /quit".to_string()));
+        assert_eq!(parse("/join .."), Err("Invalid room name '..'".to_string()));
+        assert_eq!(
+            parse("/export pdf"),
+            Err("Unknown export format 'pdf' (md, html or json)".to_string())
+        );
+        assert_eq!(
+            parse("/frobnicate"),
+            Err("Unknown command '/frobnicate' (try /help)".to_string())
+        );
+    }
+
+    #[test]
+    fn completes_command_names() {
+        assert_eq!(complete("re"), ["reconnect"]);
+        assert_eq!(complete("m"), ["msg", "me"]);
+        assert!(complete("x").is_empty());
+    }
+}
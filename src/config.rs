@@ -0,0 +1,72 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+// Settings remembered between launches, stored as TOML in the XDG config
+// directory (`~/.config/termchat/config.toml` on Linux).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nick: Option<String>,
+    // Messages kept in memory per tab before older ones are paged out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_limit: Option<usize>,
+    // Words, or /regexes/, that highlight a message the way our nick does.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub highlights: Vec<String>,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("termchat").join("config.toml"))
+    }
+
+    // A missing file is an empty config.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nick_survives_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("config-test-{}", std::process::id()));
+        let path = dir.join("termchat").join("config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        let config = Config {
+            nick: Some("alice".to_string()),
+            history_limit: Some(1000),
+            highlights: vec!["deploy".to_string(), "/rel(ease)?/".to_string()],
+        };
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
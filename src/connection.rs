@@ -0,0 +1,691 @@
+use std::{
+    fmt, io,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use rustls::ClientConfig;
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    time::Instant,
+};
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream, connect_async_tls_with_config,
+    tungstenite::{
+        self, Message,
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+    },
+};
+use url::Url;
+
+use crate::outbox::{Outbox, OutboxEntry};
+
+// Upper bound for handing a single frame to the socket. A write that takes
+// longer than this is treated as a dead connection.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Queued messages wait this long on a new connection for `Command::Resume`,
+// for servers that never greet their clients.
+pub const GREETING_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Paths tried, in order, when neither the backend URL nor --path names one.
+// The Python servers listen on `/` (main.py) and `/ws` (allinone.py).
+pub const PROBE_PATHS: [&str; 2] = ["/", "/ws"];
+
+#[derive(Clone)]
+pub struct ConnectOptions {
+    // Candidate endpoints tried in order until one accepts the upgrade.
+    pub urls: Vec<String>,
+    // Extra headers sent with every upgrade request.
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    // Only consulted for wss:// URLs.
+    pub tls: Option<Arc<ClientConfig>>,
+}
+
+impl ConnectOptions {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            urls: vec![url.into()],
+            headers: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+// Accepts either a bare `host:port` (the historical form, assumed to be
+// plain ws://) or a full ws:// / wss:// URL with path and query.
+pub fn parse_backend(backend: &str) -> io::Result<Url> {
+    let raw = if backend.contains("://") {
+        backend.to_string()
+    } else {
+        format!("ws://{}", backend)
+    };
+
+    let url = Url::parse(&raw).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid backend '{}': {}", backend, e),
+        )
+    })?;
+    match url.scheme() {
+        "ws" | "wss" => Ok(url),
+        scheme => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported scheme '{}', expected ws or wss", scheme),
+        )),
+    }
+}
+
+// Returns the endpoints to try for `url`. An explicit `path` wins, then a
+// path already present in the URL; otherwise the well-known paths are probed.
+pub fn endpoint_candidates(url: &Url, path: Option<&str>) -> Vec<String> {
+    let paths: Vec<&str> = match path {
+        Some(path) => vec![path],
+        None if url.path() != "/" => return vec![url.to_string()],
+        None => PROBE_PATHS.to_vec(),
+    };
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let mut candidate = url.clone();
+            if path.starts_with('/') {
+                candidate.set_path(path);
+            } else {
+                candidate.set_path(&format!("/{}", path));
+            }
+            candidate.to_string()
+        })
+        .collect()
+}
+
+// Parses a `Name: value` pair as given to --header.
+pub fn parse_header(raw: &str) -> io::Result<(HeaderName, HeaderValue)> {
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid header '{}': {}", raw, reason),
+        )
+    };
+
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| invalid("expected 'Name: value'".to_string()))?;
+    let name =
+        HeaderName::from_bytes(name.trim().as_bytes()).map_err(|e| invalid(e.to_string()))?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|e| invalid(e.to_string()))?;
+    Ok((name, value))
+}
+
+// Builds the frame for a queued entry when it is about to be sent, so it
+// matches whichever server is on the other end by then. Entries it returns
+// `None` for are dropped.
+pub type Encoder = Arc<dyn Fn(&OutboxEntry) -> Option<String> + Send + Sync>;
+
+// What the connection hands back to its owner.
+pub enum Inbound {
+    Frame(String),
+    // The session ended; the next one may be with a different server.
+    Closed,
+}
+
+pub enum Command {
+    // Frame that is only worth sending on a live connection; dropped while
+    // disconnected.
+    Send(String),
+    // New entries were pushed to the outbox.
+    Flush,
+    // The server's greeting has been answered, e.g. the nick claimed and
+    // rooms rejoined, so queued messages may follow.
+    Resume,
+    Reconnect,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Backoff {
+        attempt: u32,
+        retry_at: std::time::Instant,
+    },
+    Disconnected,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionStatus {
+    pub url: String,
+    pub state: ConnectionState,
+    pub last_error: Option<String>,
+}
+
+impl ConnectionStatus {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            state: ConnectionState::Connecting,
+            last_error: None,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.state {
+            ConnectionState::Connecting => write!(f, "Connecting to {}...", self.url),
+            ConnectionState::Connected => write!(f, "Connected to {}", self.url),
+            ConnectionState::Backoff { attempt, retry_at } => {
+                let remaining = retry_at.saturating_duration_since(std::time::Instant::now());
+                write!(
+                    f,
+                    "Reconnecting in {:.1}s (attempt {})",
+                    remaining.as_secs_f32(),
+                    attempt
+                )?;
+                if let Some(error) = &self.last_error {
+                    write!(f, ": {}", error)?;
+                }
+                write!(f, " - F5 to retry now")
+            }
+            ConnectionState::Disconnected => {
+                write!(f, "Disconnected")?;
+                if let Some(error) = &self.last_error {
+                    write!(f, ": {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+enum SessionEnd {
+    Lost(String),
+    Reconnect,
+    Shutdown,
+}
+
+// Starts the connection manager on its own thread and returns the sender used
+// to queue outgoing frames and control requests. The manager keeps
+// reconnecting with jittered exponential backoff until the sender is dropped,
+// and drains the outbox in order whenever a connection is up.
+pub fn spawn(
+    options: ConnectOptions,
+    ui_tx: mpsc::Sender<Inbound>,
+    status: Arc<Mutex<ConnectionStatus>>,
+    outbox: Arc<Mutex<Outbox>>,
+    encode: Encoder,
+) -> UnboundedSender<Command> {
+    let (ws_tx, ws_rx) = unbounded_channel::<Command>();
+
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                let mut status = status.lock().unwrap();
+                status.state = ConnectionState::Disconnected;
+                status.last_error = Some(format!("Runtime error: {}", e));
+                return;
+            }
+        };
+        runtime.block_on(run(options, ws_rx, ui_tx, status, outbox, encode));
+    });
+
+    ws_tx
+}
+
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let exponential = BACKOFF_BASE.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let capped = exponential.min(BACKOFF_MAX);
+    capped.mul_f64(rand::random_range(0.5..=1.0))
+}
+
+fn set_state(status: &Mutex<ConnectionStatus>, state: ConnectionState) {
+    status.lock().unwrap().state = state;
+}
+
+async fn run(
+    options: ConnectOptions,
+    mut commands: UnboundedReceiver<Command>,
+    ui_tx: mpsc::Sender<Inbound>,
+    status: Arc<Mutex<ConnectionStatus>>,
+    outbox: Arc<Mutex<Outbox>>,
+    encode: Encoder,
+) {
+    let mut attempt = 0;
+    let mut preferred = 0;
+
+    loop {
+        set_state(&status, ConnectionState::Connecting);
+
+        match connect(&options, &mut preferred, &status).await {
+            Ok(socket) => {
+                attempt = 0;
+                {
+                    let mut status = status.lock().unwrap();
+                    status.state = ConnectionState::Connected;
+                    status.last_error = None;
+                }
+                match session(socket, &mut commands, &outbox, &encode, &ui_tx).await {
+                    SessionEnd::Lost(error) => status.lock().unwrap().last_error = Some(error),
+                    SessionEnd::Reconnect => continue,
+                    SessionEnd::Shutdown => break,
+                }
+            }
+            Err(tungstenite::Error::Url(e)) => {
+                status.lock().unwrap().last_error = Some(format!("Invalid URL: {}", e));
+                break;
+            }
+            Err(e) => {
+                status.lock().unwrap().last_error = Some(format!("Connection failed: {}", e));
+            }
+        }
+
+        attempt += 1;
+        let delay = backoff_delay(attempt);
+        set_state(
+            &status,
+            ConnectionState::Backoff {
+                attempt,
+                retry_at: std::time::Instant::now() + delay,
+            },
+        );
+
+        let sleep = tokio::time::sleep_until(Instant::now() + delay);
+        tokio::pin!(sleep);
+        let shutdown = loop {
+            tokio::select! {
+                _ = &mut sleep => break false,
+                command = commands.recv() => match command {
+                    Some(Command::Send(_) | Command::Flush | Command::Resume) => {}
+                    Some(Command::Reconnect) => break false,
+                    None => break true,
+                },
+            }
+        };
+        if shutdown {
+            break;
+        }
+    }
+
+    set_state(&status, ConnectionState::Disconnected);
+}
+
+// Tries every candidate endpoint, starting with the one that worked last, and
+// returns the first socket that completes the WebSocket handshake.
+async fn connect(
+    options: &ConnectOptions,
+    preferred: &mut usize,
+    status: &Mutex<ConnectionStatus>,
+) -> Result<Socket, tungstenite::Error> {
+    let order = std::iter::once(*preferred)
+        .chain((0..options.urls.len()).filter(|&i| i != *preferred))
+        .collect::<Vec<_>>();
+
+    let mut last_error = None;
+    for index in order {
+        let url = &options.urls[index];
+        status.lock().unwrap().url = url.clone();
+
+        let mut request = url.as_str().into_client_request()?;
+        for (name, value) in &options.headers {
+            request.headers_mut().insert(name.clone(), value.clone());
+        }
+
+        let connector = options.tls.clone().map(Connector::Rustls);
+        match connect_async_tls_with_config(request, None, false, connector).await {
+            Ok((socket, _)) => {
+                *preferred = index;
+                return Ok(socket);
+            }
+            Err(e @ tungstenite::Error::Url(_)) => return Err(e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or(tungstenite::Error::ConnectionClosed))
+}
+
+// Drives one established connection. Incoming frames are read by a separate
+// task so a slow or silent peer never delays outgoing messages. The outbox
+// is held back until `Command::Resume`, or `GREETING_TIMEOUT`, so that
+// whatever answers the greeting goes out before it.
+async fn session(
+    socket: Socket,
+    commands: &mut UnboundedReceiver<Command>,
+    outbox: &Mutex<Outbox>,
+    encode: &Encoder,
+    ui_tx: &mpsc::Sender<Inbound>,
+) -> SessionEnd {
+    let (mut sink, mut stream) = socket.split();
+
+    let reader_tx = ui_tx.clone();
+    let mut reader = tokio::spawn(async move {
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    if reader_tx.send(Inbound::Frame(text.to_string())).is_err() {
+                        return "Client closed".to_string();
+                    }
+                }
+                Ok(Message::Close(_)) => return "Connection closed by server".to_string(),
+                Ok(_) => {}
+                Err(e) => return format!("Receive error: {}", e),
+            }
+        }
+        "Connection closed".to_string()
+    });
+
+    let greeting = tokio::time::sleep(GREETING_TIMEOUT);
+    tokio::pin!(greeting);
+    let mut held = true;
+    let end = loop {
+        // Queued messages always go first so they keep their original order.
+        let next = if held {
+            None
+        } else {
+            outbox.lock().unwrap().front().cloned()
+        };
+        let (outbox_id, payload) = match next {
+            Some(entry) => match encode(&entry) {
+                Some(payload) => (Some(entry.id), payload),
+                None => {
+                    outbox.lock().unwrap().remove(entry.id);
+                    continue;
+                }
+            },
+            None => tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Send(frame)) => (None, frame),
+                    Some(Command::Flush) => continue,
+                    Some(Command::Resume) => {
+                        held = false;
+                        continue;
+                    }
+                    Some(Command::Reconnect) => break SessionEnd::Reconnect,
+                    None => break SessionEnd::Shutdown,
+                },
+                _ = &mut greeting, if held => {
+                    held = false;
+                    continue;
+                }
+                result = &mut reader => {
+                    break SessionEnd::Lost(result.unwrap_or_else(|e| e.to_string()));
+                }
+            },
+        };
+
+        let frame = Message::Text(payload.into());
+        match tokio::time::timeout(SEND_TIMEOUT, sink.send(frame)).await {
+            Ok(Ok(())) => {
+                if let Some(id) = outbox_id {
+                    outbox.lock().unwrap().remove(id);
+                }
+            }
+            Ok(Err(e)) => break SessionEnd::Lost(format!("Send error: {}", e)),
+            Err(_) => break SessionEnd::Lost("Send error: timed out".to_string()),
+        }
+    };
+
+    reader.abort();
+    let _ = sink.close().await;
+    let _ = ui_tx.send(Inbound::Closed);
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{
+        accept_async, accept_hdr_async,
+        tungstenite::{
+            handshake::server::{ErrorResponse, Request, Response},
+            http::StatusCode,
+        },
+    };
+
+    async fn wait_for_state(status: &Mutex<ConnectionStatus>, state: ConnectionState) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while status.lock().unwrap().state != state {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connection never reached the expected state");
+    }
+
+    async fn next_text(server: &mut WebSocketStream<TcpStream>) -> String {
+        let message = tokio::time::timeout(Duration::from_millis(500), server.next())
+            .await
+            .expect("no message arrived in time")
+            .unwrap()
+            .unwrap();
+        message.into_text().unwrap().to_string()
+    }
+
+    struct Harness {
+        listener: TcpListener,
+        status: Arc<Mutex<ConnectionStatus>>,
+        outbox: Arc<Mutex<Outbox>>,
+        ws_tx: UnboundedSender<Command>,
+        _ui_rx: mpsc::Receiver<Inbound>,
+    }
+
+    impl Harness {
+        async fn start() -> Self {
+            Self::start_with(ConnectOptions::new).await
+        }
+
+        async fn start_with(options: impl FnOnce(String) -> ConnectOptions) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+            let (ui_tx, ui_rx) = mpsc::channel();
+            let status = Arc::new(Mutex::new(ConnectionStatus::new(&url)));
+            let outbox = Arc::new(Mutex::new(Outbox::new()));
+            let options = options(url);
+            let encode: Encoder = Arc::new(|entry: &OutboxEntry| Some(entry.text.clone()));
+            let ws_tx = spawn(
+                options,
+                ui_tx,
+                Arc::clone(&status),
+                Arc::clone(&outbox),
+                encode,
+            );
+            Self {
+                listener,
+                status,
+                outbox,
+                ws_tx,
+                _ui_rx: ui_rx,
+            }
+        }
+
+        async fn accept(&self) -> WebSocketStream<TcpStream> {
+            let (tcp, _) = tokio::time::timeout(Duration::from_secs(5), self.listener.accept())
+                .await
+                .expect("client did not connect")
+                .unwrap();
+            let server = accept_async(tcp).await.unwrap();
+            wait_for_state(&self.status, ConnectionState::Connected).await;
+            server
+        }
+
+        // What the client does once it has answered the greeting.
+        fn resume(&self) {
+            self.ws_tx.send(Command::Resume).unwrap();
+        }
+
+        fn queue(&self, text: &str) {
+            self.outbox
+                .lock()
+                .unwrap()
+                .push("general", text.to_string(), false);
+            self.ws_tx.send(Command::Flush).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn lone_client_message_is_sent_immediately() {
+        let harness = Harness::start().await;
+        let mut server = harness.accept().await;
+        harness.resume();
+
+        // The server never sends anything, so a reader-driven loop would
+        // keep this frame queued forever.
+        let sent_at = std::time::Instant::now();
+        harness.queue("hello");
+
+        assert_eq!(next_text(&mut server).await, "hello");
+        assert!(sent_at.elapsed() < Duration::from_millis(500));
+        assert!(harness.outbox.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_server_drops_connection() {
+        let harness = Harness::start().await;
+        let server = harness.accept().await;
+        drop(server);
+
+        // Skip the backoff wait instead of sleeping through it.
+        harness.ws_tx.send(Command::Reconnect).unwrap();
+        let _server = harness.accept().await;
+    }
+
+    #[tokio::test]
+    async fn outbox_is_flushed_in_order_after_reconnect() {
+        let harness = Harness::start().await;
+        let server = harness.accept().await;
+        drop(server);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !matches!(
+                harness.status.lock().unwrap().state,
+                ConnectionState::Backoff { .. }
+            ) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("client never noticed the dropped connection");
+
+        harness.queue("first");
+        harness.queue("second");
+        assert_eq!(harness.outbox.lock().unwrap().len(), 2);
+
+        harness.ws_tx.send(Command::Reconnect).unwrap();
+        let mut server = harness.accept().await;
+
+        // Whatever answers the greeting goes ahead of the queue.
+        harness
+            .ws_tx
+            .send(Command::Send("nick".to_string()))
+            .unwrap();
+        harness.resume();
+        assert_eq!(next_text(&mut server).await, "nick");
+        assert_eq!(next_text(&mut server).await, "first");
+        assert_eq!(next_text(&mut server).await, "second");
+    }
+
+    #[tokio::test]
+    async fn queue_goes_out_without_a_greeting_after_a_while() {
+        let harness = Harness::start().await;
+        harness.queue("hello");
+        let mut server = harness.accept().await;
+
+        let message = tokio::time::timeout(GREETING_TIMEOUT * 2, server.next())
+            .await
+            .expect("queue was held forever")
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.into_text().unwrap().as_str(), "hello");
+    }
+
+    #[tokio::test]
+    async fn probes_ws_path_and_sends_custom_headers() {
+        let harness = Harness::start_with(|url| {
+            let url = Url::parse(&url).unwrap();
+            ConnectOptions {
+                urls: endpoint_candidates(&url, None),
+                headers: vec![parse_header("Authorization: Bearer secret").unwrap()],
+                tls: None,
+            }
+        })
+        .await;
+
+        // Behave like allinone.py: only /ws upgrades, everything else is a 404.
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let _server = loop {
+            let (tcp, _) = harness.listener.accept().await.unwrap();
+            let seen = Arc::clone(&seen);
+            #[allow(clippy::result_large_err)]
+            let callback = move |request: &Request, response: Response| {
+                seen.lock().unwrap().push((
+                    request.uri().path().to_string(),
+                    request.headers().get("authorization").cloned(),
+                ));
+                if request.uri().path() == "/ws" {
+                    Ok(response)
+                } else {
+                    let mut rejection = ErrorResponse::new(None);
+                    *rejection.status_mut() = StatusCode::NOT_FOUND;
+                    Err(rejection)
+                }
+            };
+            if let Ok(server) = accept_hdr_async(tcp, callback).await {
+                break server;
+            }
+        };
+        wait_for_state(&harness.status, ConnectionState::Connected).await;
+
+        let seen = seen.lock().unwrap();
+        let paths: Vec<&str> = seen.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["/", "/ws"]);
+        assert!(
+            seen.iter()
+                .all(|(_, auth)| auth.as_ref().unwrap() == "Bearer secret")
+        );
+        assert!(harness.status.lock().unwrap().url.ends_with("/ws"));
+    }
+
+    #[test]
+    fn explicit_path_disables_probing() {
+        let url = parse_backend("localhost:9001").unwrap();
+        assert_eq!(
+            endpoint_candidates(&url, None),
+            ["ws://localhost:9001/", "ws://localhost:9001/ws"]
+        );
+        assert_eq!(
+            endpoint_candidates(&url, Some("chat")),
+            ["ws://localhost:9001/chat"]
+        );
+
+        let url = parse_backend("wss://example.com/socket?room=main").unwrap();
+        assert_eq!(
+            endpoint_candidates(&url, None),
+            ["wss://example.com/socket?room=main"]
+        );
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        for attempt in 1..40 {
+            let delay = backoff_delay(attempt);
+            let ceiling = BACKOFF_BASE
+                .saturating_mul(1 << (attempt - 1).min(16))
+                .min(BACKOFF_MAX);
+            assert!(delay <= ceiling);
+            assert!(delay >= ceiling / 2);
+        }
+    }
+}
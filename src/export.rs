@@ -0,0 +1,220 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde_json::json;
+
+use crate::{
+    palette::nick_colour_index,
+    transcript::{EntryKind, LogEntry},
+};
+
+// Nick colours for HTML exports, readable on a white page.
+const HTML_PALETTE: &[&str] = &[
+    "#c0392b", "#d35400", "#b7950b", "#27ae60", "#16a085", "#2980b9", "#8e44ad", "#2c3e50",
+    "#e84393", "#6c5ce7", "#00838f", "#7f8c8d",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unknown export format '{}' (md, html or json)", s)),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+// Parses the start or end of an export range: "2026-10-17" (the whole day)
+// or "2026-10-17T12:30", in local time.
+pub fn parse_time(time: &str, end: bool) -> Result<DateTime<Utc>, String> {
+    let local = if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        let day_time = if end {
+            date.and_hms_opt(23, 59, 59)
+        } else {
+            date.and_hms_opt(0, 0, 0)
+        };
+        day_time.expect("midnight and the last second are valid times")
+    } else {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+            .map_err(|_| format!("invalid time '{}' (YYYY-MM-DD or YYYY-MM-DDTHH:MM)", time))?
+    };
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("'{}' does not exist in the local time zone", time))
+}
+
+fn local_time(entry: &LogEntry) -> String {
+    entry
+        .timestamp
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+// Renders the transcript of `room` (a room name, or `@nick` for a direct
+// conversation) as a document of the given format.
+pub fn export(room: &str, entries: &[LogEntry], format: ExportFormat) -> String {
+    let title = if room.starts_with('@') {
+        room.to_string()
+    } else {
+        format!("#{}", room)
+    };
+    match format {
+        ExportFormat::Markdown => markdown(&title, entries),
+        ExportFormat::Html => html(&title, entries),
+        ExportFormat::Json => {
+            let document = json!({ "room": room, "messages": entries });
+            serde_json::to_string_pretty(&document).expect("log entries always serialize")
+        }
+    }
+}
+
+fn markdown(title: &str, entries: &[LogEntry]) -> String {
+    let mut out = format!("# {}\n\n", title);
+    for entry in entries {
+        let line = match entry.kind {
+            EntryKind::Chat | EntryKind::Direct => {
+                format!("**{}**: {}", escape_markdown(&entry.nick), entry.text)
+            }
+            _ => format!("_{}_", escape_markdown(&entry.describe())),
+        };
+        // Continuation lines are indented to stay inside the list item.
+        let line = line.replace('\n', "\n  ");
+        out.push_str(&format!("- `{}` {}\n", local_time(entry), line));
+    }
+    out
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn html(title: &str, entries: &[LogEntry]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+         body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; }}\n\
+         .message {{ margin: 0.2em 0; white-space: pre-wrap; }}\n\
+         .time {{ color: #999; font-size: 0.85em; }}\n\
+         .nick {{ font-weight: bold; }}\n\
+         .notice {{ color: #777; font-style: italic; }}\n\
+         </style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape_html(title),
+        escape_html(title)
+    );
+    for entry in entries {
+        let time = format!("<span class=\"time\">{}</span>", local_time(entry));
+        let body = match entry.kind {
+            EntryKind::Chat | EntryKind::Direct => format!(
+                "<span class=\"nick\" style=\"color: {}\">{}</span>: {}",
+                HTML_PALETTE[nick_colour_index(&entry.nick, HTML_PALETTE.len())],
+                escape_html(&entry.nick),
+                escape_html(&entry.text)
+            ),
+            _ => format!(
+                "<span class=\"notice\">{}</span>",
+                escape_html(&entry.describe())
+            ),
+        };
+        out.push_str(&format!("<div class=\"message\">{} {}</div>\n", time, body));
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<LogEntry> {
+        let timestamp = Utc::now();
+        vec![
+            LogEntry {
+                timestamp,
+                kind: EntryKind::Chat,
+                nick: "alice".to_string(),
+                text: "ship it\n<b>today</b>".to_string(),
+            },
+            LogEntry {
+                timestamp,
+                kind: EntryKind::Join,
+                nick: "bob".to_string(),
+                text: "#general".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn markdown_lists_messages() {
+        let document = export("general", &entries(), ExportFormat::Markdown);
+        let lines: Vec<&str> = document.lines().collect();
+        assert_eq!(lines[0], "# #general");
+        assert!(lines[2].ends_with("` **alice**: ship it"));
+        assert_eq!(lines[3], "  <b>today</b>");
+        assert!(lines[4].ends_with("` _bob has joined \\#general_"));
+    }
+
+    #[test]
+    fn html_is_escaped_and_coloured() {
+        let document = export("general", &entries(), ExportFormat::Html);
+        assert!(document.starts_with("<!DOCTYPE html>"));
+        assert!(document.contains("&lt;b&gt;today&lt;/b&gt;"));
+        let colour = HTML_PALETTE[nick_colour_index("alice", HTML_PALETTE.len())];
+        assert!(document.contains(&format!("style=\"color: {}\">alice</span>", colour)));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let entries = entries();
+        let document = export("general", &entries, ExportFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&document).unwrap();
+        assert_eq!(value["room"], "general");
+        let messages: Vec<LogEntry> = serde_json::from_value(value["messages"].clone()).unwrap();
+        assert_eq!(messages, entries);
+    }
+
+    #[test]
+    fn ranges_cover_whole_days() {
+        let from = parse_time("2026-10-17", false).unwrap();
+        let to = parse_time("2026-10-17", true).unwrap();
+        assert_eq!((to - from).num_seconds(), 24 * 60 * 60 - 1);
+        assert!(parse_time("2026-10-17T12:30", false).unwrap() > from);
+        assert!(parse_time("yesterday", false).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use regex::{Regex, RegexBuilder};
+
+// Longest message text put into a desktop notification.
+const MAX_NOTIFICATION_TEXT: usize = 200;
+
+// Decides which messages are meant for us: ones that name our nick, or
+// match one of the user's highlight words.
+#[derive(Debug, Default)]
+pub struct Highlighter {
+    patterns: Vec<Regex>,
+}
+
+impl Highlighter {
+    // Plain words match as whole words, ignoring case; words wrapped in
+    // slashes, like `/deploy(ed|ing)?/`, are regular expressions.
+    pub fn new(words: &[String]) -> Result<Self, String> {
+        let patterns = words
+            .iter()
+            .map(|word| {
+                let pattern = match word.strip_prefix('/').and_then(|w| w.strip_suffix('/')) {
+                    Some(pattern) if !pattern.is_empty() => pattern.to_string(),
+                    _ => format!(r"\b{}\b", regex::escape(word)),
+                };
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("Invalid highlight '{}': {}", word, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_highlight(&self, text: &str, nick: &str) -> bool {
+        mentions_nick(text, nick) || self.patterns.iter().any(|re| re.is_match(text))
+    }
+}
+
+// Whether `text` names `nick` as a word of its own, ignoring case: "alice:"
+// and "@alice" do, "malice" does not.
+pub fn mentions_nick(text: &str, nick: &str) -> bool {
+    if nick.is_empty() {
+        return false;
+    }
+    let text = text.to_lowercase();
+    let nick = nick.to_lowercase();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(&nick).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + nick.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+// Escape sequences asking the terminal for a desktop notification: OSC 777
+// (VTE, foot, urxvt) and OSC 9 (iTerm2, Windows Terminal), with a bell for
+// terminals that understand neither. Control characters are dropped so a
+// message cannot smuggle in escape sequences of its own.
+pub fn notification(title: &str, text: &str) -> String {
+    let clean = |text: &str, limit: usize| -> String {
+        text.chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .take(limit)
+            .collect()
+    };
+    let title = clean(title, MAX_NOTIFICATION_TEXT).replace(';', ",");
+    let text = clean(text, MAX_NOTIFICATION_TEXT);
+    format!(
+        "\x1b]777;notify;{};{}\x07\x1b]9;{}: {}\x07\x07",
+        title, text, title, text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nick_mentions_are_whole_words() {
+        assert!(mentions_nick("Alice: ship it?", "alice"));
+        assert!(mentions_nick("thanks @alice!", "alice"));
+        assert!(!mentions_nick("no malice intended", "alice"));
+        assert!(!mentions_nick("alice_bot says hi", "alice"));
+        assert!(!mentions_nick("anything", ""));
+    }
+
+    #[test]
+    fn highlight_words_and_regexes() {
+        let words = ["Deploy".to_string(), "/rele?ase[sd]?/".to_string()];
+        let highlighter = Highlighter::new(&words).unwrap();
+        assert!(highlighter.is_highlight("deploy is done", "bob"));
+        assert!(!highlighter.is_highlight("redeployed", "bob"));
+        assert!(highlighter.is_highlight("we released it", "bob"));
+        assert!(highlighter.is_highlight("bob: look", "bob"));
+        assert!(!highlighter.is_highlight("nothing here", "bob"));
+
+        assert!(Highlighter::new(&["/(/".to_string()]).is_err());
+    }
+
+    #[test]
+    fn notifications_drop_control_characters() {
+        let sequence = notification("#general; alice", "hi\x1b]0;pwned\x07");
+        assert_eq!(
+            sequence,
+            "\x1b]777;notify;#general, alice;hi ]0;pwned \x07\x1b]9;#general, alice: hi ]0;pwned \x07\x07"
+        );
+    }
+}
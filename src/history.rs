@@ -0,0 +1,320 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::protocol::Incoming;
+
+// Messages kept in memory per tab unless configured otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 5000;
+// The spill file remembers where every this-many-th message starts, so
+// reading back seeks close to it without indexing every message.
+const INDEX_EVERY: usize = 128;
+
+// Messages paged out of memory, oldest first, one JSON string per line
+// holding the frame as received. The file belongs to this process only and
+// is removed when dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    end: u64,
+    // Byte offsets of messages 0, INDEX_EVERY, 2 * INDEX_EVERY, ...
+    index: Vec<u64>,
+}
+
+impl SpillFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            len: 0,
+            end: 0,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, message: &Incoming) -> io::Result<()> {
+        let mut line = serde_json::to_string(&message.to_frame()).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(line.as_bytes())?;
+        if self.len.is_multiple_of(INDEX_EVERY) {
+            self.index.push(self.end);
+        }
+        self.end += line.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    // The messages in `range`, oldest first.
+    pub fn read(&mut self, range: Range<usize>) -> io::Result<Vec<Incoming>> {
+        let range = range.start.min(self.len)..range.end.min(self.len);
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let page = range.start / INDEX_EVERY;
+        self.file.seek(SeekFrom::Start(self.index[page]))?;
+        BufReader::new(&self.file)
+            .lines()
+            .skip(range.start - page * INDEX_EVERY)
+            .take(range.len())
+            .map(|line| {
+                let frame: String = serde_json::from_str(&line?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(Incoming::parse(&frame))
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.len = 0;
+        self.end = 0;
+        self.index.clear();
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// A tab's messages, of which only the newest `limit` stay in memory once
+// `trim` is called. Older ones are written to the spill file, if there is
+// one, and read back a page at a time with `load_older`; without a file
+// they are forgotten.
+#[derive(Debug)]
+pub struct History {
+    limit: usize,
+    window: VecDeque<Incoming>,
+    // Position of `window[0]` in the whole history. Everything before it
+    // is in `spill`.
+    first: usize,
+    spill: Option<SpillFile>,
+}
+
+impl History {
+    pub fn new(limit: usize, spill: Option<SpillFile>) -> Self {
+        Self {
+            limit,
+            window: VecDeque::new(),
+            first: 0,
+            spill,
+        }
+    }
+
+    pub fn push(&mut self, message: Incoming) {
+        self.window.push_back(message);
+    }
+
+    // Pages out the oldest messages beyond the limit, returning how many
+    // left memory.
+    pub fn trim(&mut self) -> usize {
+        let excess = self.window.len().saturating_sub(self.limit);
+        for message in self.window.drain(..excess) {
+            // Messages read back earlier are still in the file.
+            if let Some(spill) = &mut self.spill
+                && spill.len() == self.first
+                && spill.append(&message).is_err()
+            {
+                // Without a working file older messages are just dropped.
+                self.spill = None;
+            }
+            self.first += 1;
+        }
+        excess
+    }
+
+    // Whether `load_older` has anything to read back.
+    pub fn has_older(&self) -> bool {
+        self.first > 0 && self.spill.is_some()
+    }
+
+    // Reads up to `count` of the messages just before the ones in memory
+    // back in, returning how many were added to the front.
+    pub fn load_older(&mut self, count: usize) -> io::Result<usize> {
+        let Some(spill) = &mut self.spill else {
+            return Ok(0);
+        };
+        let start = self.first.saturating_sub(count);
+        let older = spill.read(start..self.first)?;
+        let loaded = older.len();
+        for message in older.into_iter().rev() {
+            self.window.push_front(message);
+        }
+        self.first -= loaded;
+        Ok(loaded)
+    }
+
+    // Adds messages older than all others, such as history fetched from the
+    // server. Only valid while `has_older` is false: then nothing is on disk
+    // that is not also in memory, so the spill file can start over.
+    pub fn prepend(&mut self, messages: Vec<Incoming>) {
+        debug_assert!(!self.has_older());
+        if self.first == 0
+            && let Some(spill) = &mut self.spill
+            && spill.clear().is_err()
+        {
+            self.spill = None;
+        }
+        for message in messages.into_iter().rev() {
+            self.window.push_front(message);
+        }
+    }
+
+    // Every message still kept, including the ones paged out, oldest
+    // first. It starts at position `first_kept` of the whole history.
+    pub fn all(&mut self) -> io::Result<Vec<Incoming>> {
+        let mut messages = match &mut self.spill {
+            Some(spill) => spill.read(0..self.first)?,
+            None => Vec::new(),
+        };
+        messages.extend(self.window.iter().cloned());
+        Ok(messages)
+    }
+
+    // How many of the oldest messages left memory, i.e. the position of
+    // the first message in memory in the whole history.
+    pub fn paged_out(&self) -> usize {
+        self.first
+    }
+
+    // Position of the first message `all` returns: 0, unless older ones
+    // were dropped for want of a spill file.
+    pub fn first_kept(&self) -> usize {
+        if self.spill.is_some() { 0 } else { self.first }
+    }
+
+    // The messages in memory, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Incoming> {
+        self.window.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.window.clear();
+        self.first = 0;
+        if let Some(spill) = &mut self.spill
+            && spill.clear().is_err()
+        {
+            self.spill = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(history: &History) -> Vec<String> {
+        history
+            .iter()
+            .map(|message| match message {
+                Incoming::Legacy(text) => text.clone(),
+                Incoming::Event(event) => format!("{:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn old_messages_spill_to_disk_and_come_back() {
+        let path = std::env::temp_dir().join(format!("history-test-{}.jsonl", std::process::id()));
+        let mut history = History::new(3, Some(SpillFile::create(&path).unwrap()));
+        for i in 0..300 {
+            history.push(Incoming::Legacy(format!("message {}\nline two", i)));
+        }
+        assert_eq!(history.trim(), 297);
+        assert_eq!(history.len(), 3);
+        assert!(history.has_older());
+        assert_eq!(history.all().unwrap().len(), 300);
+
+        assert_eq!(history.load_older(140).unwrap(), 140);
+        assert_eq!(history.len(), 143);
+        assert_eq!(texts(&history)[0], "message 157\nline two");
+        assert_eq!(history.load_older(1000).unwrap(), 157);
+        assert_eq!(texts(&history)[0], "message 0\nline two");
+        assert!(!history.has_older());
+
+        // Trimming again does not write the same messages twice.
+        history.push(Incoming::Legacy("message 300".to_string()));
+        assert_eq!(history.trim(), 298);
+        assert_eq!(history.load_older(2).unwrap(), 2);
+        assert_eq!(texts(&history)[0], "message 296\nline two");
+
+        // History from elsewhere goes in front once everything is back.
+        history.load_older(1000).unwrap();
+        history.prepend(vec![Incoming::Legacy("older".to_string())]);
+        assert_eq!(history.trim(), 299);
+        history.load_older(1).unwrap();
+        assert_eq!(texts(&history)[0], "message 297\nline two");
+        history.load_older(1000).unwrap();
+        assert_eq!(texts(&history)[0], "older");
+
+        drop(history);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn without_a_file_old_messages_are_dropped() {
+        let mut history = History::new(2, None);
+        for i in 0..5 {
+            history.push(Incoming::Legacy(i.to_string()));
+        }
+        assert_eq!(history.trim(), 3);
+        assert_eq!(texts(&history), ["3", "4"]);
+        assert!(!history.has_older());
+        assert_eq!(history.load_older(10).unwrap(), 0);
+    }
+
+    #[test]
+    fn without_a_file_positions_start_after_the_dropped_messages() {
+        let mut history = History::new(2, None);
+        for i in 0..5 {
+            history.push(Incoming::Legacy(i.to_string()));
+        }
+        history.trim();
+        assert_eq!(history.all().unwrap().len(), 2);
+        assert_eq!(history.paged_out(), 3);
+        assert_eq!(history.first_kept(), 3);
+
+        let path = std::env::temp_dir().join(format!("history-kept-{}.jsonl", std::process::id()));
+        let mut history = History::new(2, Some(SpillFile::create(&path).unwrap()));
+        for i in 0..5 {
+            history.push(Incoming::Legacy(i.to_string()));
+        }
+        history.trim();
+        assert_eq!(history.all().unwrap().len(), 5);
+        assert_eq!(history.first_kept(), 0);
+    }
+}
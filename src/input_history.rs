@@ -0,0 +1,212 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// Entries kept across sessions.
+pub const DEFAULT_INPUT_HISTORY: usize = 1000;
+
+// Messages and commands typed earlier, oldest first, for Up/Down and
+// Ctrl+R. With a file they survive restarts: one JSON string per line, so
+// multi-line entries come back whole.
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    limit: usize,
+    path: Option<PathBuf>,
+    // Lines in the file, which is only rewritten once it holds twice the
+    // limit.
+    lines: usize,
+    // Entry shown while browsing with Up/Down, and the unsent text to
+    // return to past the newest one.
+    browsing: Option<usize>,
+    draft: String,
+}
+
+impl InputHistory {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    // `~/.local/share/termchat/input_history.jsonl` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("termchat").join("input_history.jsonl"))
+    }
+
+    // A missing file is an empty history; lines that do not parse are
+    // skipped.
+    pub fn load(path: &Path, limit: usize) -> io::Result<Self> {
+        let mut history = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::new(limit)
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+        for line in contents.lines() {
+            history.lines += 1;
+            if let Ok(entry) = serde_json::from_str::<String>(line)
+                && history.entries.last() != Some(&entry)
+            {
+                history.entries.push(entry);
+            }
+        }
+        let excess = history.entries.len().saturating_sub(limit);
+        history.entries.drain(..excess);
+        Ok(history)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    // Records a sent entry, unless it repeats the last one, and stops
+    // browsing.
+    pub fn add(&mut self, entry: &str) -> io::Result<()> {
+        self.browsing = None;
+        self.draft.clear();
+        if self.entries.last().is_some_and(|last| last == entry) {
+            return Ok(());
+        }
+        self.entries.push(entry.to_string());
+        let excess = self.entries.len().saturating_sub(self.limit);
+        self.entries.drain(..excess);
+        self.save(entry)
+    }
+
+    // The entry before the one shown; `current` is the text being edited,
+    // kept for when browsing comes back down. Stays on the oldest entry.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let index = match self.browsing {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.browsing = Some(index);
+        self.get(index)
+    }
+
+    // The entry after the one shown, or the text that was being edited
+    // after the newest one.
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.browsing?;
+        if index + 1 < self.entries.len() {
+            self.browsing = Some(index + 1);
+            self.get(index + 1)
+        } else {
+            self.browsing = None;
+            Some(&self.draft)
+        }
+    }
+
+    // The newest entry before `before` that contains `query`, ignoring
+    // case, for reverse-i-search.
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.to_lowercase().contains(&query))
+    }
+
+    fn save(&mut self, entry: &str) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let line = |entry: &str| serde_json::to_string(entry).expect("strings always serialize");
+        if self.lines + 1 > self.limit * 2 {
+            let contents: String = self
+                .entries
+                .iter()
+                .map(|entry| line(entry) + "\n")
+                .collect();
+            fs::write(path, contents)?;
+            self.lines = self.entries.len();
+        } else {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line(entry))?;
+            self.lines += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> InputHistory {
+        let mut history = InputHistory::new(10);
+        for entry in entries {
+            history.add(entry).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn browsing_stops_at_the_ends_and_restores_the_draft() {
+        let mut history = history(&["one", "two", "two", "three"]);
+        assert_eq!(history.len(), 3);
+
+        assert_eq!(history.newer(), None);
+        assert_eq!(history.older("dra"), Some("three"));
+        assert_eq!(history.older("three"), Some("two"));
+        assert_eq!(history.older("two"), Some("one"));
+        assert_eq!(history.older("one"), Some("one"));
+        assert_eq!(history.newer(), Some("two"));
+        assert_eq!(history.newer(), Some("three"));
+        assert_eq!(history.newer(), Some("dra"));
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn reverse_search_goes_back_from_a_position() {
+        let history = history(&["deploy now", "hi", "Deploy later", "ok"]);
+        assert_eq!(history.search("deploy", history.len()), Some(2));
+        assert_eq!(history.search("deploy", 2), Some(0));
+        assert_eq!(history.search("deploy", 0), None);
+        assert_eq!(history.search("nope", 4), None);
+    }
+
+    #[test]
+    fn multi_line_entries_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("input-history-test-{}", std::process::id()));
+        let path = dir.join("termchat").join("input_history.jsonl");
+        let mut history = InputHistory::load(&path, 3).unwrap();
+        assert!(history.is_empty());
+        history.add("first\nsecond").unwrap();
+        history.add("b").unwrap();
+
+        let mut history = InputHistory::load(&path, 3).unwrap();
+        assert_eq!(history.get(0), Some("first\nsecond"));
+
+        // Past twice the limit the file is rewritten with what is kept.
+        for entry in ["c", "d", "e", "f", "g"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        let history = InputHistory::load(&path, 3).unwrap();
+        assert_eq!(history.entries, ["e", "f", "g"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::VecDeque;
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// Continuation rows are only indented when at least this many columns are
+// left for the text; narrower panes wrap flush left instead.
+const MIN_TEXT_WIDTH: usize = 10;
+
+// A laid out row: runs of text, each drawn in one style.
+pub type Row<S> = Vec<(String, S)>;
+
+// Lays out one message as terminal rows of at most `width` columns: `prefix`
+// (e.g. "[12:00:00] alice: ") starts the first row, and every following row,
+// whether from a newline in `text` or from wrapping, is indented to line up
+// under the text. Lines are broken at spaces where possible and inside words
+// that do not fit on a row of their own.
+pub fn wrap_message(prefix: &str, text: &str, width: usize) -> Vec<String> {
+    wrap_styled(&[(prefix.to_string(), ())], text, (), width)
+        .into_iter()
+        .map(|row| row.into_iter().map(|(text, ())| text).collect())
+        .collect()
+}
+
+// `wrap_message` for a prefix made of differently styled parts (timestamp,
+// nick, ...) and text in `style`. Runs are split wherever a row breaks.
+pub fn wrap_styled<S: Clone + PartialEq>(
+    prefix: &[(String, S)],
+    text: &str,
+    style: S,
+    width: usize,
+) -> Vec<Row<S>> {
+    let width = width.max(1);
+    let prefix_width: usize = prefix.iter().map(|(part, _)| part.width()).sum();
+    let indent = if prefix_width + MIN_TEXT_WIDTH <= width {
+        prefix_width
+    } else {
+        0
+    };
+
+    let mut rows = Rows {
+        rows: Vec::new(),
+        row: Vec::new(),
+        row_width: 0,
+        has_text: false,
+        width,
+        indent,
+        style,
+    };
+    for (part, part_style) in prefix {
+        rows.push_chars(part, part_style);
+    }
+    let style = rows.style.clone();
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            rows.next_row();
+        }
+        for word in split_keeping_spaces(line) {
+            rows.push_word(word, &style);
+        }
+    }
+    rows.finish()
+}
+
+// The wrapped rows of a whole history, laid out once per message as it
+// arrives and again only when the width changes, so drawing a frame only
+// touches the rows on screen. `S` is the style the renderer draws text
+// with.
+#[derive(Debug)]
+pub struct MessageLines<S> {
+    // Zero until the first `set_width`; nothing is laid out before then.
+    width: usize,
+    messages: VecDeque<LaidOut<S>>,
+    // Where each message's rows start, counted from an arbitrary origin so
+    // that dropping the oldest message does not shift every entry; the
+    // first message starts at row `starts[0] - starts[0]`.
+    starts: VecDeque<usize>,
+    // Where the row after the last one would start, in the same terms.
+    end: usize,
+    // Label and style of the divider row drawn above one message, and
+    // whether it moves to the next message pushed.
+    divider: Option<(String, S)>,
+    divide_next: bool,
+}
+
+#[derive(Debug)]
+struct LaidOut<S> {
+    prefix: Row<S>,
+    text: String,
+    style: S,
+    // Whether the divider is drawn above this message.
+    divided: bool,
+    rows: Vec<Row<S>>,
+}
+
+impl<S> Default for MessageLines<S> {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            messages: VecDeque::new(),
+            starts: VecDeque::new(),
+            end: 0,
+            divider: None,
+            divide_next: false,
+        }
+    }
+}
+
+impl<S: Clone + PartialEq> MessageLines<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, prefix: Row<S>, text: String, style: S) {
+        let divided = std::mem::take(&mut self.divide_next);
+        if divided {
+            self.remove_divider();
+        }
+        let message = self.lay_out(prefix, text, style, divided);
+        self.starts.push_back(self.end);
+        self.end += message.rows.len();
+        self.messages.push_back(message);
+    }
+
+    // Adds a page of messages, oldest first, before the oldest one,
+    // returning how many rows they take so a scroll position can be kept on
+    // the same text. Rows are numbered afresh once per page.
+    pub fn extend_front(
+        &mut self,
+        messages: impl IntoIterator<Item = (Row<S>, String, S)>,
+    ) -> usize {
+        let page: Vec<_> = messages
+            .into_iter()
+            .map(|(prefix, text, style)| self.lay_out(prefix, text, style, false))
+            .collect();
+        let rows = page.iter().map(|message| message.rows.len()).sum();
+        for message in page.into_iter().rev() {
+            self.messages.push_front(message);
+        }
+        self.reindex();
+        rows
+    }
+
+    // Drops the oldest message, returning how many rows it took.
+    pub fn pop_front(&mut self) -> usize {
+        let Some(message) = self.messages.pop_front() else {
+            return 0;
+        };
+        self.starts.pop_front();
+        if self.messages.is_empty() {
+            self.end = 0;
+        }
+        message.rows.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.starts.clear();
+        self.end = 0;
+    }
+
+    // Lays everything out again if the width changed, e.g. after a resize.
+    pub fn set_width(&mut self, width: usize) {
+        if width == self.width {
+            return;
+        }
+        self.width = width;
+        for message in self.messages.iter_mut() {
+            message.rows = lay_out_rows(message, width, self.divider.as_ref());
+        }
+        self.reindex();
+    }
+
+    // Moves the divider, a row reading `label` across the pane, above the
+    // next message pushed, e.g. the first one to arrive while the user is
+    // away. Until then it stays where it is.
+    pub fn divide_next(&mut self, label: &str, style: S) {
+        self.divider = Some((label.to_string(), style));
+        self.divide_next = true;
+    }
+
+    pub fn cancel_divide_next(&mut self) {
+        self.divide_next = false;
+    }
+
+    // The row the divider is drawn on, if a message in memory has it.
+    pub fn divider_row(&self) -> Option<usize> {
+        let index = self.messages.iter().position(|message| message.divided)?;
+        Some(self.starts[index] - self.origin())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.end - self.origin()
+    }
+
+    // The row message `index` starts on.
+    pub fn message_row(&self, index: usize) -> Option<usize> {
+        let start = self.starts.get(index)?;
+        Some(start - self.origin())
+    }
+
+    // Up to `count` rows starting at row `start`.
+    pub fn rows(&self, start: usize, count: usize) -> impl Iterator<Item = &[(String, S)]> {
+        let start = start + self.origin();
+        let first = self.starts.partition_point(|&row| row <= start).max(1) - 1;
+        let skip = start - self.starts.get(first).copied().unwrap_or(start);
+        self.messages
+            .range(first..)
+            .flat_map(|message| message.rows.iter().map(Vec::as_slice))
+            .skip(skip)
+            .take(count)
+    }
+
+    fn origin(&self) -> usize {
+        self.starts.front().copied().unwrap_or(self.end)
+    }
+
+    fn lay_out(&self, prefix: Row<S>, text: String, style: S, divided: bool) -> LaidOut<S> {
+        let mut message = LaidOut {
+            prefix,
+            text,
+            style,
+            divided,
+            rows: Vec::new(),
+        };
+        if self.width > 0 {
+            message.rows = lay_out_rows(&message, self.width, self.divider.as_ref());
+        }
+        message
+    }
+
+    fn remove_divider(&mut self) {
+        let Some(message) = self.messages.iter_mut().find(|message| message.divided) else {
+            return;
+        };
+        message.divided = false;
+        if self.width > 0 {
+            message.rows = lay_out_rows(message, self.width, None);
+        }
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.starts.clear();
+        self.end = 0;
+        for message in &self.messages {
+            self.starts.push_back(self.end);
+            self.end += message.rows.len();
+        }
+    }
+}
+
+fn lay_out_rows<S: Clone + PartialEq>(
+    message: &LaidOut<S>,
+    width: usize,
+    divider: Option<&(String, S)>,
+) -> Vec<Row<S>> {
+    let mut rows = Vec::new();
+    if message.divided
+        && let Some((label, style)) = divider
+    {
+        rows.push(vec![(divider_text(label, width), style.clone())]);
+    }
+    rows.extend(wrap_styled(
+        &message.prefix,
+        &message.text,
+        message.style.clone(),
+        width,
+    ));
+    rows
+}
+
+// "──── label ────" across `width` columns, or just the line when the label
+// does not fit.
+fn divider_text(label: &str, width: usize) -> String {
+    let label = format!(" {} ", label);
+    let fill = width.saturating_sub(label.width());
+    if fill < 2 {
+        return "─".repeat(width);
+    }
+    format!(
+        "{}{}{}",
+        "─".repeat(fill / 2),
+        label,
+        "─".repeat(fill - fill / 2)
+    )
+}
+
+struct Rows<S> {
+    rows: Vec<Row<S>>,
+    row: Row<S>,
+    row_width: usize,
+    // Whether the current row holds message text, not just prefix or indent.
+    has_text: bool,
+    width: usize,
+    indent: usize,
+    // The message text's style, which the indent is drawn in too.
+    style: S,
+}
+
+impl<S: Clone + PartialEq> Rows<S> {
+    fn next_row(&mut self) {
+        let row = std::mem::take(&mut self.row);
+        self.rows.push(trim_end(row));
+        if self.indent > 0 {
+            self.row.push((" ".repeat(self.indent), self.style.clone()));
+        }
+        self.row_width = self.indent;
+        self.has_text = false;
+    }
+
+    // Adds a word and the spaces after it, moving to a new row first when it
+    // does not fit but would on an empty one.
+    fn push_word(&mut self, word: &str, style: &S) {
+        let text = word.trim_end_matches(' ');
+        let spaces = &word[text.len()..];
+        let text_width = text.width();
+        if self.row_width + text_width > self.width
+            && self.row_width > self.indent
+            && (self.has_text || text_width <= self.width - self.indent)
+        {
+            self.next_row();
+        }
+        self.push_chars(text, style);
+        self.has_text |= !text.is_empty();
+        // Trailing spaces may run past the edge; they are trimmed when the
+        // row ends.
+        self.push_str(spaces, style);
+        self.row_width += spaces.len();
+    }
+
+    // Adds text character by character, breaking rows wherever it overflows.
+    fn push_chars(&mut self, text: &str, style: &S) {
+        for c in text.chars() {
+            let char_width = c.width().unwrap_or(0);
+            if self.row_width + char_width > self.width && self.row_width > self.indent {
+                self.next_row();
+            }
+            self.push_str(c.encode_utf8(&mut [0; 4]), style);
+            self.row_width += char_width;
+        }
+    }
+
+    // Appends to the last run when the style matches, so a row has as few
+    // runs as it has styles.
+    fn push_str(&mut self, text: &str, style: &S) {
+        match self.row.last_mut() {
+            Some((run, run_style)) if run_style == style => run.push_str(text),
+            _ if text.is_empty() => {}
+            _ => self.row.push((text.to_string(), style.clone())),
+        }
+    }
+
+    fn finish(mut self) -> Vec<Row<S>> {
+        let row = std::mem::take(&mut self.row);
+        self.rows.push(trim_end(row));
+        self.rows
+    }
+}
+
+// Drops whitespace at the end of a row, and runs left empty by that.
+fn trim_end<S>(mut row: Row<S>) -> Row<S> {
+    while let Some((run, _)) = row.last_mut() {
+        run.truncate(run.trim_end().len());
+        if !run.is_empty() {
+            break;
+        }
+        row.pop();
+    }
+    row
+}
+
+// Splits `line` into words, each followed by the spaces after it. Leading
+// spaces come out as a word of their own, so indentation is kept.
+fn split_keeping_spaces(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = false;
+    for (i, c) in line.char_indices() {
+        if c == ' ' {
+            in_space = true;
+        } else if in_space {
+            words.push(&line[start..i]);
+            start = i;
+            in_space = false;
+        }
+    }
+    if start < line.len() {
+        words.push(&line[start..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::LegacyLine;
+
+    #[test]
+    fn wraps_at_spaces_with_hanging_indent() {
+        assert_eq!(
+            wrap_message("bob: ", "the quick brown fox jumps", 20),
+            ["bob: the quick brown", "     fox jumps"]
+        );
+        assert_eq!(
+            wrap_message("bob: ", "one\ntwo", 20),
+            ["bob: one", "     two"]
+        );
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_row() {
+        assert_eq!(
+            wrap_message("a: ", "abcdefghijklmnopqrstuvwxyz", 16),
+            ["a: abcdefghijklm", "   nopqrstuvwxyz"]
+        );
+    }
+
+    #[test]
+    fn counts_wide_characters_by_column() {
+        let rows = wrap_message("", "日本語のテキスト", 6);
+        assert_eq!(rows, ["日本語", "のテキ", "スト"]);
+        assert!(rows.iter().all(|row| row.width() <= 6));
+    }
+
+    #[test]
+    fn drops_indent_when_the_pane_is_narrow() {
+        assert_eq!(
+            wrap_message("[12:00:00] alice: ", "hello world", 20),
+            ["[12:00:00] alice:", "hello world"]
+        );
+    }
+
+    #[test]
+    fn styled_runs_are_split_where_rows_break() {
+        let prefix = [
+            ("[1] ".to_string(), 1),
+            ("bob".to_string(), 2),
+            (": ".to_string(), 3),
+        ];
+        let rows = wrap_styled(&prefix, "one two three four", 0, 20);
+        let run = |text: &str, style| (text.to_string(), style);
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    run("[1] ", 1),
+                    run("bob", 2),
+                    run(": ", 3),
+                    run("one two", 0)
+                ],
+                vec![run("         three four", 0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_lines_wrap_under_their_text() {
+        let line =
+            LegacyLine::parse("[12:00:00] alice: the quick brown fox\n                  jumps")
+                .unwrap();
+        let prefix = format!("[{}] {}: ", line.time, line.nick);
+        assert_eq!(
+            wrap_message(&prefix, &line.text, 30),
+            [
+                "[12:00:00] alice: the quick",
+                "                  brown fox",
+                "                  jumps",
+            ]
+        );
+    }
+
+    fn texts<'a>(rows: impl Iterator<Item = &'a [(String, u8)]>) -> Vec<String> {
+        rows.map(|row| row.iter().map(|(text, _)| text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn cached_rows_follow_the_width() {
+        let prefix = |text: &str| vec![(text.to_string(), 0)];
+        let mut lines = MessageLines::new();
+        lines.push(prefix("a: "), "one two three".to_string(), 1);
+        lines.push(prefix("b: "), "four".to_string(), 2);
+        assert_eq!(lines.row_count(), 0);
+
+        lines.set_width(13);
+        assert_eq!(lines.row_count(), 3);
+        assert_eq!(texts(lines.rows(1, 5)), ["   three", "b: four"]);
+        assert_eq!(lines.rows(2, 1).next().unwrap()[1], ("four".to_string(), 2));
+
+        lines.set_width(40);
+        assert_eq!(lines.row_count(), 2);
+        assert_eq!(texts(lines.rows(1, 1)), ["b: four"]);
+        assert_eq!(lines.rows(2, 1).count(), 0);
+
+        assert_eq!(lines.pop_front(), 1);
+        let older = [
+            (prefix("y: "), "minus one".to_string(), 0),
+            (prefix("z: "), "zero".to_string(), 0),
+        ];
+        assert_eq!(lines.extend_front(older), 2);
+        lines.push(prefix("c: "), "five".to_string(), 3);
+        assert_eq!(
+            texts(lines.rows(0, 10)),
+            ["y: minus one", "z: zero", "b: four", "c: five"]
+        );
+        assert_eq!(lines.message_row(2), Some(2));
+    }
+
+    #[test]
+    fn divider_goes_above_the_next_message() {
+        let prefix = |text: &str| vec![(text.to_string(), 0)];
+        let mut lines = MessageLines::new();
+        lines.set_width(16);
+        lines.push(prefix("a: "), "one".to_string(), 0);
+        assert_eq!(lines.divider_row(), None);
+
+        lines.divide_next("new", 1);
+        lines.push(prefix("b: "), "two".to_string(), 0);
+        assert_eq!(lines.divider_row(), Some(1));
+        assert_eq!(
+            texts(lines.rows(0, 10)),
+            ["a: one", "───── new ──────", "b: two"]
+        );
+
+        // Nothing arrived while away: the divider stays put.
+        lines.divide_next("new", 1);
+        lines.cancel_divide_next();
+        lines.push(prefix("c: "), "three".to_string(), 0);
+        assert_eq!(lines.divider_row(), Some(1));
+
+        lines.divide_next("new", 1);
+        lines.push(prefix("d: "), "four".to_string(), 0);
+        assert_eq!(lines.divider_row(), Some(3));
+        assert_eq!(lines.row_count(), 5);
+
+        lines.set_width(4);
+        assert_eq!(texts(lines.rows(lines.divider_row().unwrap(), 1)), ["────"]);
+    }
+}
@@ -0,0 +1,18 @@
+pub mod client;
+pub mod command;
+pub mod config;
+pub mod connection;
+pub mod export;
+pub mod highlight;
+pub mod history;
+pub mod input_history;
+pub mod layout;
+pub mod outbox;
+pub mod palette;
+pub mod protocol;
+pub mod search;
+pub mod server;
+pub mod store;
+pub mod tls;
+pub mod transcript;
+pub mod typing;
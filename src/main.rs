@@ -1,310 +1,1059 @@
 use std::{
-    io,
-    sync::{Arc, Mutex, mpsc},
-    thread,
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    rc::Rc,
+    sync::mpsc,
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use chrono::{DateTime, Local, Utc};
+use clap::{Parser, Subcommand};
 use ratatui::{
     Frame, Terminal,
     backend::CrosstermBackend,
     crossterm::{
-        event::{Event, KeyCode, poll, read},
+        event::{
+            DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event,
+            KeyCode, KeyEvent, KeyModifiers, MouseEventKind, poll, read,
+        },
         execute,
-        terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+        style::Print,
+        terminal::{
+            EnterAlternateScreen, LeaveAlternateScreen, SetTitle, disable_raw_mode, enable_raw_mode,
+        },
     },
-    layout::{Constraint, Layout},
-    style::{Color, Style},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    widgets::{Block, Borders, Paragraph, Tabs, Wrap},
 };
-use serde_json;
-use tungstenite::{Message, connect};
+use termchat::{
+    client::ChatClient,
+    command::{self, COMMANDS, Input, SlashCommand},
+    config::Config,
+    connection::{self, ConnectOptions, ConnectionState},
+    export::{self, ExportFormat},
+    highlight::{self, Highlighter},
+    history::{DEFAULT_HISTORY_LIMIT, History, SpillFile},
+    input_history::{DEFAULT_INPUT_HISTORY, InputHistory},
+    layout::{self, MessageLines, Row},
+    outbox::Outbox,
+    palette::{self, ColorDepth},
+    protocol::{DEFAULT_ROOM, Incoming, LegacyLine, Presence, ServerEvent, StoredMessage},
+    search::{self, Search},
+    tls::{self, TlsOptions},
+    transcript::{self, LogEntry, LogFormat, TranscriptLog},
+    typing::{self, TypingNotifier, TypingTracker},
+};
+
+const MOUSE_SCROLL_LINES: usize = 3;
+// Messages read back from disk, or asked from the server, at a time when
+// scrolling past the top.
+const LOAD_OLDER_PAGE: usize = 200;
+
+// xterm's title stack: the user's window title is saved before ours is set,
+// and put back on exit.
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
+// No key press for this long marks the user as idle.
+const IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Nickname; defaults to the last one chosen with /nick
     #[arg(short, long)]
-    nick: String,
+    nick: Option<String>,
 
+    /// Server address: host:port, or a full ws:// or wss:// URL
     #[arg(short, long, default_value = "localhost:9001")]
     backend: String,
+
+    /// WebSocket endpoint path; when omitted `/` and `/ws` are probed
+    #[arg(long)]
+    path: Option<String>,
+
+    /// Extra HTTP header for the upgrade request, e.g. "Authorization: Bearer x"
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+
+    /// Extra PEM CA bundle trusted for wss:// connections
+    #[arg(long)]
+    ca_file: Option<PathBuf>,
+
+    /// Skip TLS certificate verification (local testing only)
+    #[arg(long)]
+    insecure: bool,
+
+    /// PEM client certificate for mutual TLS
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM private key for --client-cert
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Keep unsent messages in this file so they survive a crash
+    #[arg(long)]
+    outbox_file: Option<PathBuf>,
+
+    /// Messages kept in memory per tab; older ones are paged out to disk
+    #[arg(long)]
+    history_limit: Option<usize>,
+
+    /// Append every message to per-room, per-day log files in this directory
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+
+    /// Log file format: plain (irssi-style) or jsonl
+    #[arg(long, default_value = "plain", requires = "log_dir")]
+    log_format: LogFormat,
+
+    /// Rotate a log file once it would grow past this size, e.g. 10M
+    #[arg(long, value_parser = transcript::parse_size, requires = "log_dir")]
+    log_max_size: Option<u64>,
+
+    /// Rotated log files kept per day
+    #[arg(long, default_value_t = 5, requires = "log_max_size")]
+    log_keep: usize,
+
+    /// Word, or /regex/, that highlights a message like our nick does (repeatable)
+    #[arg(long = "highlight", value_name = "WORD")]
+    highlights: Vec<String>,
+
+    /// Colours to draw with: 16, 256 or truecolor; detected when omitted
+    #[arg(long)]
+    colors: Option<ColorDepth>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Write logged history of a room as Markdown, HTML or JSON
+    Export(ExportArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    /// Directory the messages were logged to with --log-dir
+    #[arg(long)]
+    log_dir: PathBuf,
+
+    /// Room to export, or @nick for a direct conversation
+    #[arg(long, default_value = DEFAULT_ROOM)]
+    room: String,
+
+    /// md, html or json
+    #[arg(short, long, default_value = "md")]
+    format: ExportFormat,
+
+    /// Only messages from this day or time on: YYYY-MM-DD or YYYY-MM-DDTHH:MM
+    #[arg(long)]
+    from: Option<String>,
+
+    /// Only messages up to this day or time
+    #[arg(long)]
+    to: Option<String>,
+
+    /// File to write; standard output when omitted
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn run_export(args: &ExportArgs) -> io::Result<()> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let from = args
+        .from
+        .as_deref()
+        .map(|from| export::parse_time(from, false))
+        .transpose()
+        .map_err(invalid)?;
+    let to = args
+        .to
+        .as_deref()
+        .map(|to| export::parse_time(to, true))
+        .transpose()
+        .map_err(invalid)?;
+    let room = args.room.trim_start_matches('#');
+    let entries = TranscriptLog::new(&args.log_dir, LogFormat::default()).read(room, from, to)?;
+    let document = export::export(room, &entries, args.format);
+    match &args.output {
+        Some(path) => fs::write(path, document),
+        None => io::stdout().write_all(document.as_bytes()),
+    }
 }
 
 fn main() -> io::Result<()> {
     let args = Args::parse();
-    let ws_url = format!("ws://{}", args.backend);
+    if let Some(Commands::Export(export_args)) = &args.command {
+        return run_export(export_args);
+    }
+    let config_path = Config::default_path();
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let input_history = match InputHistory::default_path() {
+        Some(path) => InputHistory::load(&path, DEFAULT_INPUT_HISTORY)?,
+        None => InputHistory::new(DEFAULT_INPUT_HISTORY),
+    };
+    let Some(nick) = args.nick.clone().or_else(|| config.nick.clone()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no nickname: pass --nick, it is remembered after the first /nick",
+        ));
+    };
+    let backend_url = connection::parse_backend(&args.backend)?;
+    let headers = args
+        .headers
+        .iter()
+        .map(|header| connection::parse_header(header))
+        .collect::<io::Result<Vec<_>>>()?;
+    let tls = if backend_url.scheme() == "wss" {
+        Some(tls::client_config(&TlsOptions {
+            ca_file: args.ca_file.clone(),
+            insecure: args.insecure,
+            client_cert: args.client_cert.clone(),
+            client_key: args.client_key.clone(),
+        })?)
+    } else {
+        None
+    };
+    let outbox = match &args.outbox_file {
+        Some(path) => Outbox::load(path)?,
+        None => Outbox::new(),
+    };
+    let highlight_words: Vec<String> = config
+        .highlights
+        .iter()
+        .chain(&args.highlights)
+        .cloned()
+        .collect();
+    let highlighter = Highlighter::new(&highlight_words)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
     let mut stdout = io::stdout();
     enable_raw_mode()?;
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(
+        stdout,
+        Print(PUSH_TITLE),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableFocusChange
+    )?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let (ws_tx, ws_rx) = mpsc::channel::<String>();
-    let (ui_tx, ui_rx) = mpsc::channel::<String>();
-    let messages = Arc::new(Mutex::new(Vec::<String>::new()));
-    let connection_status = Arc::new(Mutex::new(format!("Connecting to {}...", ws_url)));
-
-    let status_clone = Arc::clone(&connection_status);
-    let ui_tx_clone = ui_tx.clone();
-    let url_clone = ws_url.clone();
-    thread::spawn(move || match connect(&url_clone) {
-        Ok((mut socket, _)) => {
-            *status_clone.lock().unwrap() = format!("Connected to {}", url_clone);
-
-            loop {
-                if let Ok(msg) = ws_rx.try_recv() {
-                    if let Err(e) = socket.send(Message::Text(msg.into())) {
-                        *status_clone.lock().unwrap() = format!("Send error: {}", e);
-                        break;
-                    }
-                }
+    let urls = connection::endpoint_candidates(&backend_url, args.path.as_deref());
+    let client = ChatClient::connect(ConnectOptions { urls, headers, tls }, nick, outbox);
+    let ui_rx = client.subscribe();
 
-                match socket.read() {
-                    Ok(message) => {
-                        if let Message::Text(text) = message {
-                            ui_tx_clone.send(text.to_string()).unwrap();
-                        }
-                    }
-                    Err(tungstenite::Error::Io(ref err))
-                        if err.kind() == io::ErrorKind::WouldBlock =>
-                    {
-                        thread::sleep(Duration::from_millis(10));
-                    }
-                    Err(e) => {
-                        *status_clone.lock().unwrap() = format!("Receive error: {}", e);
-                        break;
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            *status_clone.lock().unwrap() = format!("Connection failed: {}", e);
+    let transcript = args.log_dir.as_ref().map(|dir| {
+        let log = TranscriptLog::new(dir, args.log_format);
+        match args.log_max_size {
+            Some(max_size) => log.with_rotation(max_size, args.log_keep),
+            None => log,
         }
     });
-
+    let history_limit = args
+        .history_limit
+        .or(config.history_limit)
+        .unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let spill_dir = dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("termchat")
+        .join("spill");
+    let mentions_spill = spill_dir.join(format!("{}-mentions.jsonl", std::process::id()));
+    let mentions = Room::mentions(History::new(
+        history_limit,
+        SpillFile::create(&mentions_spill).ok(),
+    ));
     let mut app = App {
         exit: false,
         new_message_text: vec!["".to_string()],
         cursor_position: 0,
-        messages,
-        ws_tx,
-        input_history: Vec::new(),
-        history_index: 0,
-        connection_status,
-        scroll_offset: 0,
-        scroll_to_bottom: true,
-        nick: args.nick,
+        rooms: Vec::new(),
+        active_room: 0,
+        client,
+        input_history,
+        reverse_search: None,
+        status_note: None,
+        config,
+        config_path,
+        pending_nick: None,
+        presence: HashMap::new(),
+        show_users: true,
+        last_activity: Instant::now(),
+        typing: TypingNotifier::default(),
+        typists: TypingTracker::default(),
+        page_height: 0,
+        dirty: true,
+        drawn_status: StatusSnapshot::default(),
+        history_limit,
+        spill_dir,
+        spill_files: 0,
+        transcript,
+        colors: args.colors.unwrap_or_else(ColorDepth::detect),
+        highlighter: Rc::new(highlighter),
+        mentions,
+        show_mentions: false,
+        focused: true,
+        unfocused_mentions: 0,
+        alerts: Vec::new(),
+        window_title: String::new(),
+        search: None,
+        search_logs: None,
     };
+    let room = app.new_room(DEFAULT_ROOM);
+    app.rooms.push(room);
 
     let app_result = app.run(&mut terminal, ui_rx);
 
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableFocusChange,
+        Print(POP_TITLE)
+    )?;
     terminal.show_cursor()?;
 
     app_result
 }
 
+fn local_time(timestamp: &DateTime<Utc>) -> String {
+    timestamp
+        .with_timezone(&Local)
+        .format("%H:%M:%S")
+        .to_string()
+}
+
+// How messages are drawn: nicks in a colour derived from the nick, in as
+// many colours as the terminal has, with our own messages standing out.
+struct Theme {
+    depth: ColorDepth,
+    own_nick: String,
+    highlighter: Rc<Highlighter>,
+}
+
+impl Theme {
+    // Messages from others that name us or a highlight word.
+    fn is_highlight(&self, nick: &str, text: &str) -> bool {
+        nick != self.own_nick && self.highlighter.is_highlight(text, &self.own_nick)
+    }
+
+    fn highlighted(&self, style: Style) -> Style {
+        let style = style.bg(palette::highlight_background(self.depth));
+        match self.depth {
+            ColorDepth::Basic => style.fg(Color::Black),
+            _ => style,
+        }
+    }
+
+    fn timestamp(&self, timestamp: &DateTime<Utc>) -> (String, Style) {
+        self.clock(&local_time(timestamp))
+    }
+
+    // A time already formatted as "12:00:00".
+    fn clock(&self, time: &str) -> (String, Style) {
+        (format!("[{}] ", time), Style::default().fg(Color::DarkGray))
+    }
+
+    fn nick(&self, nick: &str) -> (String, Style) {
+        let style = Style::default().fg(palette::nick_colour(nick, self.depth));
+        let style = if nick == self.own_nick {
+            style.add_modifier(Modifier::BOLD)
+        } else {
+            style
+        };
+        (nick.to_string(), style)
+    }
+
+    fn text(&self, nick: &str) -> Style {
+        if nick == self.own_nick {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        }
+    }
+}
+
+// Turns a received frame into a prefix ("[12:00:00] alice: ") made of styled
+// parts, the message text wrapped after it, and the style of the text.
+// `labelled` adds the room to chat messages, for views that mix rooms.
+// Legacy frames from the Python servers are split back into the same parts
+// where they follow the usual format, and shown as they are otherwise.
+fn render_incoming(
+    incoming: &Incoming,
+    theme: &Theme,
+    labelled: bool,
+) -> Option<(Row<Style>, String, Style)> {
+    let notice_style = Style::default()
+        .fg(Color::Gray)
+        .add_modifier(Modifier::ITALIC);
+    let notice = |clock: (String, Style), text: String| {
+        (
+            vec![clock, ("System: ".to_string(), notice_style)],
+            text,
+            notice_style,
+        )
+    };
+
+    let event = match incoming {
+        Incoming::Legacy(frame) => {
+            let Some(line) = LegacyLine::parse(frame) else {
+                return Some((Vec::new(), frame.clone(), Style::default()));
+            };
+            if line.nick == "System" {
+                return Some(notice(theme.clock(line.time), line.text));
+            }
+            let prefix = vec![
+                theme.clock(line.time),
+                theme.nick(line.nick),
+                (": ".to_string(), Style::default()),
+            ];
+            let style = theme.text(line.nick);
+            return Some(highlight(theme, line.nick, prefix, line.text, style));
+        }
+        Incoming::Event(event) => event,
+    };
+
+    let error = |text: String| {
+        let style = Style::default().fg(Color::Red);
+        (vec![("Error: ".to_string(), style)], text, style)
+    };
+
+    let (mut prefix, text, style) = match event {
+        ServerEvent::Welcome { .. }
+        | ServerEvent::Roster { .. }
+        | ServerEvent::PresenceChange { .. }
+        | ServerEvent::History { .. }
+        | ServerEvent::Typing { .. } => return None,
+        ServerEvent::Chat {
+            timestamp,
+            nick,
+            text,
+            action: true,
+            ..
+        } => {
+            let style = theme.text(nick).add_modifier(Modifier::ITALIC);
+            let (nick, nick_style) = theme.nick(nick);
+            (
+                vec![
+                    theme.timestamp(timestamp),
+                    ("* ".to_string(), style),
+                    (nick, nick_style.add_modifier(Modifier::ITALIC)),
+                    (" ".to_string(), style),
+                ],
+                text.clone(),
+                style,
+            )
+        }
+        ServerEvent::Chat {
+            timestamp,
+            nick,
+            text,
+            ..
+        } => (
+            vec![
+                theme.timestamp(timestamp),
+                theme.nick(nick),
+                (": ".to_string(), Style::default()),
+            ],
+            text.clone(),
+            theme.text(nick),
+        ),
+        ServerEvent::System { timestamp, text } => notice(theme.timestamp(timestamp), text.clone()),
+        ServerEvent::Join {
+            timestamp,
+            room,
+            nick,
+        } => notice(
+            theme.timestamp(timestamp),
+            format!("{} joined #{}", nick, room),
+        ),
+        ServerEvent::Leave {
+            timestamp,
+            room,
+            nick,
+        } => notice(
+            theme.timestamp(timestamp),
+            format!("{} left #{}", nick, room),
+        ),
+        ServerEvent::NickChange {
+            timestamp,
+            old_nick,
+            new_nick,
+        } => notice(
+            theme.timestamp(timestamp),
+            format!("{} is now known as {}", old_nick, new_nick),
+        ),
+        ServerEvent::Direct {
+            timestamp,
+            from,
+            to,
+            text,
+        } => {
+            let style = Style::default().fg(Color::Magenta);
+            (
+                vec![
+                    theme.timestamp(timestamp),
+                    theme.nick(from),
+                    (" → ".to_string(), style),
+                    theme.nick(to),
+                    (": ".to_string(), style),
+                ],
+                text.clone(),
+                style,
+            )
+        }
+        ServerEvent::NickRejected { nick, reason } => {
+            error(format!("cannot use nickname '{}': {}", nick, reason))
+        }
+        ServerEvent::Error { message } => error(message.clone()),
+    };
+
+    if let ServerEvent::Chat { room, nick, .. } = event {
+        if labelled {
+            let label = (format!("#{} ", room), Style::default().fg(Color::DarkGray));
+            prefix.insert(1, label);
+        }
+        return Some(highlight(theme, nick, prefix, text, style));
+    }
+    Some((prefix, text, style))
+}
+
+// Marks the whole message when it is from someone else and names us or a
+// highlight word.
+fn highlight(
+    theme: &Theme,
+    nick: &str,
+    mut prefix: Row<Style>,
+    text: String,
+    style: Style,
+) -> (Row<Style>, String, Style) {
+    if !theme.is_highlight(nick, &text) {
+        return (prefix, text, style);
+    }
+    for (_, part_style) in prefix.iter_mut() {
+        *part_style = theme.highlighted(*part_style);
+    }
+    (prefix, text, theme.highlighted(style))
+}
+
+// A joined room or a direct conversation (named `@nick`) shown as one tab.
+// Each keeps its own history, scroll position and half-typed message; the
+// draft of the active tab lives in the editor instead.
+struct Room {
+    name: String,
+    messages: History,
+    // The messages in memory as wrapped rows, one entry per message.
+    lines: MessageLines<Style>,
+    // First visible row, and whether the view sticks to the newest
+    // message. Scrolling up unpins it; new messages then only add to
+    // `unseen` instead of moving the view.
+    scroll_offset: usize,
+    follow: bool,
+    unseen: usize,
+    unread: usize,
+    draft: Vec<String>,
+    draft_cursor: usize,
+    members: BTreeSet<String>,
+    // Ids of the oldest and newest stored messages seen, for asking the
+    // server for older ones and for placing replayed ones.
+    oldest_id: Option<u64>,
+    newest_id: Option<u64>,
+    // Whether the server has messages older than `oldest_id`, and whether
+    // they have been asked for already.
+    server_has_older: bool,
+    loading_older: bool,
+    // Holds messages from every room, like the mentions view, so each one
+    // is labelled with its room.
+    labelled: bool,
+}
+
+impl Room {
+    fn new(name: &str, messages: History) -> Self {
+        Self {
+            name: name.to_string(),
+            messages,
+            lines: MessageLines::new(),
+            scroll_offset: 0,
+            follow: true,
+            unseen: 0,
+            unread: 0,
+            draft: vec!["".to_string()],
+            draft_cursor: 0,
+            members: BTreeSet::new(),
+            oldest_id: None,
+            newest_id: None,
+            server_has_older: false,
+            loading_older: false,
+            labelled: false,
+        }
+    }
+
+    // The view of everything that mentioned us, from any tab. Its name is
+    // one no room or conversation can have.
+    fn mentions(messages: History) -> Self {
+        Self {
+            labelled: true,
+            ..Self::new("", messages)
+        }
+    }
+
+    fn push(&mut self, incoming: Incoming, theme: &Theme) {
+        let Some((prefix, text, style)) = render_incoming(&incoming, theme, self.labelled) else {
+            return;
+        };
+        if let Incoming::Event(ServerEvent::Chat { id: Some(id), .. }) = &incoming {
+            self.oldest_id = self.oldest_id.min(Some(*id)).or(Some(*id));
+            self.newest_id = self.newest_id.max(Some(*id));
+        }
+        self.lines.push(prefix, text, style);
+        self.messages.push(incoming);
+        if self.follow {
+            self.trim();
+        } else {
+            self.unseen += 1;
+        }
+    }
+
+    // Back at the newest message, the history goes back down to its limit.
+    fn scroll_to_bottom(&mut self) {
+        self.follow = true;
+        self.unseen = 0;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        for _ in 0..self.messages.trim() {
+            self.lines.pop_front();
+        }
+    }
+
+    // Files stored messages from the server: older than anything shown they
+    // go on top, newer ones (missed while reconnecting) at the bottom, and
+    // ones already shown are skipped.
+    fn add_history(&mut self, messages: &[StoredMessage], more: bool, theme: &Theme) {
+        self.loading_older = false;
+        let (older, newer): (Vec<_>, Vec<_>) = messages
+            .iter()
+            .filter(|message| {
+                Some(message.id) < self.oldest_id || Some(message.id) > self.newest_id
+            })
+            .partition(|message| self.oldest_id.is_none_or(|oldest| message.id < oldest));
+
+        // Older messages paged out to disk have to come back first.
+        if !older.is_empty() && !self.messages.has_older() {
+            self.server_has_older = more;
+            self.oldest_id = Some(older[0].id);
+            self.newest_id = self.newest_id.max(older.last().map(|message| message.id));
+            let events: Vec<Incoming> = older
+                .iter()
+                .map(|message| Incoming::Event(message.to_event(&self.name)))
+                .collect();
+            let rendered = events
+                .iter()
+                .filter_map(|event| render_incoming(event, theme, self.labelled));
+            self.scroll_offset += self.lines.extend_front(rendered);
+            self.messages.prepend(events);
+        }
+        for message in newer {
+            self.push(Incoming::Event(message.to_event(&self.name)), theme);
+        }
+    }
+
+    // Reads a page of paged-out messages back in above the others, keeping
+    // the view on the same rows. Returns how many came back.
+    fn load_older(&mut self, theme: &Theme) -> io::Result<usize> {
+        let loaded = self.messages.load_older(LOAD_OLDER_PAGE)?;
+        let older = self
+            .messages
+            .iter()
+            .take(loaded)
+            .filter_map(|incoming| render_incoming(incoming, theme, self.labelled));
+        self.scroll_offset += self.lines.extend_front(older);
+        Ok(loaded)
+    }
+
+    // The other side of a direct conversation.
+    fn direct_peer(&self) -> Option<&str> {
+        self.name.strip_prefix('@')
+    }
+
+    fn tab_title(&self) -> Line<'static> {
+        let (title, style) = match self.direct_peer() {
+            _ if self.labelled => ("mentions".to_string(), Style::default().fg(Color::Yellow)),
+            Some(_) => (self.name.clone(), Style::default().fg(Color::Magenta)),
+            None => (format!("#{}", self.name), Style::default()),
+        };
+        let title = if self.unread > 0 {
+            format!("{} ({})", title, self.unread)
+        } else {
+            title
+        };
+        Line::from(Span::styled(title, style))
+    }
+}
+
 pub struct App {
     exit: bool,
     new_message_text: Vec<String>,
     cursor_position: usize,
-    messages: Arc<Mutex<Vec<String>>>,
-    ws_tx: mpsc::Sender<String>,
-    input_history: Vec<String>,
-    history_index: usize,
-    connection_status: Arc<Mutex<String>>,
-    scroll_offset: usize,
-    scroll_to_bottom: bool,
-    nick: String,
+    rooms: Vec<Room>,
+    active_room: usize,
+    client: ChatClient,
+    // Messages and commands sent, kept across sessions, and the Ctrl+R
+    // search through them.
+    input_history: InputHistory,
+    reverse_search: Option<ReverseSearch>,
+    status_note: Option<(String, Color)>,
+    config: Config,
+    config_path: Option<PathBuf>,
+    // Nick asked for with /nick, saved to the config once the server accepts
+    // it.
+    pending_nick: Option<String>,
+    // Last known presence of everyone in any joined room.
+    presence: HashMap<String, Presence>,
+    show_users: bool,
+    last_activity: Instant,
+    typing: TypingNotifier,
+    typists: TypingTracker,
+    // Height of the message pane at the last draw, for paging.
+    page_height: usize,
+    // Frames are only drawn when something changed: `dirty` covers input
+    // and messages, `drawn_status` what changes on its own.
+    dirty: bool,
+    drawn_status: StatusSnapshot,
+    // Messages kept in memory per tab, and where older ones are paged out.
+    history_limit: usize,
+    spill_dir: PathBuf,
+    spill_files: usize,
+    // Where messages are logged with --log-dir.
+    transcript: Option<TranscriptLog>,
+    colors: ColorDepth,
+    highlighter: Rc<Highlighter>,
+    // Messages that mentioned us, shown instead of the active tab with F3.
+    mentions: Room,
+    show_mentions: bool,
+    // Whether the terminal has focus, as far as it reports; mentions that
+    // arrive without it ring the bell, ask for a desktop notification and
+    // are counted in the window title.
+    focused: bool,
+    unfocused_mentions: usize,
+    alerts: Vec<String>,
+    window_title: String,
+    // Ctrl+F search over the messages on screen, or over the logs of the
+    // active tab, which are then shown instead.
+    search: Option<Search>,
+    search_logs: Option<Room>,
+}
+
+// Ctrl+R reverse-i-search through the input history, like readline's.
+struct ReverseSearch {
+    query: String,
+    // Entry found, shown in the editor, and whether the last query
+    // matched anything.
+    found: Option<usize>,
+    failing: bool,
+    // Text and cursor to put back on Esc.
+    draft: Vec<String>,
+    draft_cursor: usize,
+}
+
+// The parts of the screen that change without a key press or a message: the
+// connection status (with its countdown), the pending count and who is
+// typing.
+#[derive(Default, PartialEq)]
+struct StatusSnapshot {
+    status: String,
+    pending: usize,
+    typists: Vec<String>,
 }
 
 impl App {
     fn run(
         &mut self,
         terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-        ui_rx: mpsc::Receiver<String>,
+        ui_rx: mpsc::Receiver<Incoming>,
     ) -> io::Result<()> {
         let mut last_tab_time: Option<Instant> = None;
 
         loop {
             while let Ok(msg) = ui_rx.try_recv() {
-                let mut messages = self.messages.lock().unwrap();
-                messages.push(msg);
-                self.scroll_to_bottom = true;
+                self.route_incoming(msg);
+                self.dirty = true;
+            }
+            if self.client.presence() == Presence::Active
+                && self.last_activity.elapsed() >= IDLE_AFTER
+            {
+                self.client.set_presence(Presence::Idle);
+            }
+            if self.typing.tick(Instant::now()) {
+                self.announce_typing(false);
             }
 
-            if poll(Duration::from_millis(16))? {
-                if let Event::Key(key_event) = read()? {
-                    match key_event.code {
-                        KeyCode::Tab => {
-                            last_tab_time = Some(Instant::now());
-                        }
-                        KeyCode::Enter => {
-                            if let Some(time) = last_tab_time {
-                                if time.elapsed() < Duration::from_millis(500) {
-                                    let (line_idx, char_idx) = self.get_cursor_line_char_index();
-                                    let byte_idx = {
-                                        let line = &self.new_message_text[line_idx];
-                                        line.char_indices()
-                                            .nth(char_idx)
-                                            .map(|(i, _)| i)
-                                            .unwrap_or_else(|| line.len())
-                                    };
-
-                                    let right_part =
-                                        self.new_message_text[line_idx].split_off(byte_idx);
-                                    self.new_message_text.insert(line_idx + 1, right_part);
-                                    self.cursor_position += 1;
-                                } else {
-                                    self.send_message();
-                                }
+            let event = if poll(Duration::from_millis(16))? {
+                self.dirty = true;
+                Some(read()?)
+            } else {
+                None
+            };
+            match &event {
+                Some(Event::FocusGained) => {
+                    self.focused = true;
+                    self.unfocused_mentions = 0;
+                    for room in &mut self.rooms {
+                        room.lines.cancel_divide_next();
+                    }
+                }
+                // The first message to arrive while away gets the divider.
+                Some(Event::FocusLost) => {
+                    self.focused = false;
+                    let style = Style::default().fg(Color::LightRed);
+                    for room in &mut self.rooms {
+                        room.lines.divide_next("new messages", style);
+                    }
+                }
+                _ => {}
+            }
+            self.write_alerts(terminal.backend_mut())?;
+            if let Some(Event::Mouse(mouse)) = &event {
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => self.scroll_by(-(MOUSE_SCROLL_LINES as isize)),
+                    MouseEventKind::ScrollDown => self.scroll_by(MOUSE_SCROLL_LINES as isize),
+                    _ => {}
+                }
+            }
+            if let Some(Event::Key(key_event)) = event {
+                self.status_note = None;
+                self.last_activity = Instant::now();
+                if self.client.presence() == Presence::Idle {
+                    self.client.set_presence(Presence::Active);
+                }
+                let text_before = self.new_message_text.clone();
+                let room_before = self.active_room;
+                match key_event.code {
+                    _ if self.search.is_some() => self.search_key(key_event),
+                    // Keys that end a reverse search without using it fall
+                    // through to the editor.
+                    _ if self.reverse_search.is_some() && self.reverse_search_key(key_event) => {}
+                    KeyCode::Char('r') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.reverse_search = Some(ReverseSearch {
+                            query: String::new(),
+                            found: None,
+                            failing: false,
+                            draft: self.new_message_text.clone(),
+                            draft_cursor: self.cursor_position,
+                        });
+                    }
+                    KeyCode::Char('f') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.start_search();
+                    }
+                    KeyCode::Tab if !self.complete_command() => {
+                        last_tab_time = Some(Instant::now());
+                    }
+                    KeyCode::Enter => {
+                        if let Some(time) = last_tab_time {
+                            if time.elapsed() < Duration::from_millis(500) {
+                                let (line_idx, char_idx) = self.get_cursor_line_char_index();
+                                let byte_idx = {
+                                    let line = &self.new_message_text[line_idx];
+                                    line.char_indices()
+                                        .nth(char_idx)
+                                        .map(|(i, _)| i)
+                                        .unwrap_or_else(|| line.len())
+                                };
+
+                                let right_part =
+                                    self.new_message_text[line_idx].split_off(byte_idx);
+                                self.new_message_text.insert(line_idx + 1, right_part);
+                                self.cursor_position += 1;
                             } else {
                                 self.send_message();
                             }
+                        } else {
+                            self.send_message();
                         }
-                        KeyCode::Esc => {
-                            self.exit = true;
-                        }
-                        KeyCode::Up => {
-                            if self.new_message_text.len() == 1 {
-                                if !self.input_history.is_empty() {
-                                    if self.history_index == 0 {
-                                        self.history_index = self.input_history.len();
-                                    } else {
-                                        self.history_index -= 1;
-                                    }
-                                    if self.input_history.len() > self.history_index {
-                                        self.new_message_text[0] =
-                                            self.input_history[self.history_index].clone();
-                                        self.cursor_position = self.new_message_text[0].len();
-                                    }
-                                }
-                            } else {
-                                let (current_line, current_col) = self.get_cursor_line_char_index();
-                                if current_line > 0 {
-                                    let prev_line = &self.new_message_text[current_line - 1];
-                                    let prev_line_chars = prev_line.chars().count();
-                                    let new_col = current_col.min(prev_line_chars);
-
-                                    let mut new_position = 0;
-                                    for i in 0..(current_line - 1) {
-                                        new_position += self.new_message_text[i].chars().count();
-                                    }
-                                    new_position += new_col;
-                                    new_position += current_line - 1;
-
-                                    self.cursor_position = new_position;
-                                }
-                            }
-                        }
-                        KeyCode::Down => {
-                            if self.new_message_text.len() == 1 {
-                                if !self.input_history.is_empty() {
-                                    self.history_index =
-                                        (self.history_index + 1) % self.input_history.len();
-                                    self.new_message_text[0] =
-                                        self.input_history[self.history_index].clone();
-                                    self.cursor_position = self.new_message_text[0].len();
-                                }
-                            } else {
-                                let (current_line, current_col) = self.get_cursor_line_char_index();
-                                if current_line < self.new_message_text.len() - 1 {
-                                    let next_line = &self.new_message_text[current_line + 1];
-                                    let next_line_chars = next_line.chars().count();
-                                    let new_col = current_col.min(next_line_chars);
-
-                                    let mut new_position = 0;
-                                    for i in 0..=current_line {
-                                        new_position += self.new_message_text[i].chars().count();
-                                    }
-                                    new_position += new_col;
-                                    new_position += current_line;
-
-                                    self.cursor_position = new_position;
-                                }
+                    }
+                    KeyCode::Esc => {
+                        self.exit = true;
+                    }
+                    KeyCode::F(2) => {
+                        self.show_users = !self.show_users;
+                    }
+                    KeyCode::F(3) => {
+                        self.show_mentions = !self.show_mentions;
+                        self.mentions.unread = 0;
+                    }
+                    KeyCode::F(4) => {
+                        self.jump_to_divider();
+                    }
+                    KeyCode::F(5) => {
+                        self.client.reconnect();
+                    }
+                    KeyCode::Char('x') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.cancel_pending_message();
+                    }
+                    KeyCode::Char(c @ '1'..='9')
+                        if key_event.modifiers.contains(KeyModifiers::ALT) =>
+                    {
+                        self.switch_room(c as usize - '1' as usize);
+                    }
+                    KeyCode::PageUp if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        let count = self.rooms.len();
+                        self.switch_room((self.active_room + count - 1) % count);
+                    }
+                    KeyCode::PageDown if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.switch_room((self.active_room + 1) % self.rooms.len());
+                    }
+                    // Up on the first line and Down on the last one browse
+                    // the input history; elsewhere they move the cursor.
+                    KeyCode::Up => {
+                        let (current_line, current_col) = self.get_cursor_line_char_index();
+                        if current_line == 0 {
+                            let current = self.new_message_text.join("\n");
+                            if let Some(entry) = self.input_history.older(&current) {
+                                let entry = entry.to_string();
+                                self.set_input(&entry);
                             }
-                        }
-                        KeyCode::Left => {
-                            if self.cursor_position > 0 {
-                                self.cursor_position -= 1;
+                        } else {
+                            let prev_line = &self.new_message_text[current_line - 1];
+                            let prev_line_chars = prev_line.chars().count();
+                            let new_col = current_col.min(prev_line_chars);
+
+                            let mut new_position = 0;
+                            for i in 0..(current_line - 1) {
+                                new_position += self.new_message_text[i].chars().count();
                             }
+                            new_position += new_col;
+                            new_position += current_line - 1;
+
+                            self.cursor_position = new_position;
                         }
-                        KeyCode::Right => {
-                            let total_chars = self.get_total_chars();
-                            if self.cursor_position < total_chars {
-                                self.cursor_position += 1;
+                    }
+                    KeyCode::Down => {
+                        let (current_line, current_col) = self.get_cursor_line_char_index();
+                        if current_line == self.new_message_text.len() - 1 {
+                            if let Some(entry) = self.input_history.newer() {
+                                let entry = entry.to_string();
+                                self.set_input(&entry);
                             }
-                        }
-                        KeyCode::Backspace => {
-                            if self.cursor_position > 0 {
-                                let (line_idx, char_idx) = self.get_cursor_line_char_index();
-                                if char_idx > 0 {
-                                    let line = &mut self.new_message_text[line_idx];
-                                    let (byte_idx, c) = line
-                                        .char_indices()
-                                        .nth(char_idx - 1)
-                                        .expect("Character should exist");
-                                    let char_len = c.len_utf8();
-                                    line.drain(byte_idx..byte_idx + char_len);
-                                    self.cursor_position -= 1;
-                                } else if line_idx > 0 {
-                                    let current_line = self.new_message_text.remove(line_idx);
-                                    let prev_line = &mut self.new_message_text[line_idx - 1];
-                                    prev_line.push_str(&current_line);
-                                    self.cursor_position -= 1;
-                                }
+                        } else {
+                            let next_line = &self.new_message_text[current_line + 1];
+                            let next_line_chars = next_line.chars().count();
+                            let new_col = current_col.min(next_line_chars);
+
+                            let mut new_position = 0;
+                            for i in 0..=current_line {
+                                new_position += self.new_message_text[i].chars().count();
                             }
+                            new_position += new_col;
+                            new_position += current_line;
+
+                            self.cursor_position = new_position;
                         }
-                        KeyCode::Delete => {
-                            let total_chars = self.get_total_chars();
-                            if self.cursor_position < total_chars {
-                                let (line_idx, char_idx) = self.get_cursor_line_char_index();
-                                let line_char_count =
-                                    self.new_message_text[line_idx].chars().count();
-                                if char_idx < line_char_count {
-                                    let line = &mut self.new_message_text[line_idx];
-                                    let (byte_idx, c) = line
-                                        .char_indices()
-                                        .nth(char_idx)
-                                        .expect("Character should exist");
-                                    let char_len = c.len_utf8();
-                                    line.drain(byte_idx..byte_idx + char_len);
-                                } else if line_idx < self.new_message_text.len() - 1 {
-                                    let next_line = self.new_message_text.remove(line_idx + 1);
-                                    let line = &mut self.new_message_text[line_idx];
-                                    line.push_str(&next_line);
-                                }
-                            }
+                    }
+                    KeyCode::Left if self.cursor_position > 0 => {
+                        self.cursor_position -= 1;
+                    }
+                    KeyCode::Right => {
+                        let total_chars = self.get_total_chars();
+                        if self.cursor_position < total_chars {
+                            self.cursor_position += 1;
                         }
-                        KeyCode::Char(c) => {
-                            let (line_idx, char_idx) = self.get_cursor_line_char_index();
+                    }
+                    KeyCode::Backspace if self.cursor_position > 0 => {
+                        let (line_idx, char_idx) = self.get_cursor_line_char_index();
+                        if char_idx > 0 {
                             let line = &mut self.new_message_text[line_idx];
-                            let byte_idx = line
+                            let (byte_idx, c) = line
                                 .char_indices()
-                                .nth(char_idx)
-                                .map(|(i, _)| i)
-                                .unwrap_or_else(|| line.len());
-                            line.insert(byte_idx, c);
-                            self.cursor_position += 1;
+                                .nth(char_idx - 1)
+                                .expect("Character should exist");
+                            let char_len = c.len_utf8();
+                            line.drain(byte_idx..byte_idx + char_len);
+                            self.cursor_position -= 1;
+                        } else if line_idx > 0 {
+                            let current_line = self.new_message_text.remove(line_idx);
+                            let prev_line = &mut self.new_message_text[line_idx - 1];
+                            prev_line.push_str(&current_line);
+                            self.cursor_position -= 1;
                         }
-                        KeyCode::Home => {
-                            let (line_idx, _) = self.get_cursor_line_char_index();
-                            self.cursor_position = self.get_line_start(line_idx);
-                        }
-                        KeyCode::End => {
-                            let (line_idx, _) = self.get_cursor_line_char_index();
-                            self.cursor_position = self.get_line_end(line_idx);
+                    }
+                    KeyCode::Delete => {
+                        let total_chars = self.get_total_chars();
+                        if self.cursor_position < total_chars {
+                            let (line_idx, char_idx) = self.get_cursor_line_char_index();
+                            let line_char_count = self.new_message_text[line_idx].chars().count();
+                            if char_idx < line_char_count {
+                                let line = &mut self.new_message_text[line_idx];
+                                let (byte_idx, c) = line
+                                    .char_indices()
+                                    .nth(char_idx)
+                                    .expect("Character should exist");
+                                let char_len = c.len_utf8();
+                                line.drain(byte_idx..byte_idx + char_len);
+                            } else if line_idx < self.new_message_text.len() - 1 {
+                                let next_line = self.new_message_text.remove(line_idx + 1);
+                                let line = &mut self.new_message_text[line_idx];
+                                line.push_str(&next_line);
+                            }
                         }
-                        _ => {}
                     }
+                    KeyCode::Char(c) => {
+                        let (line_idx, char_idx) = self.get_cursor_line_char_index();
+                        let line = &mut self.new_message_text[line_idx];
+                        let byte_idx = line
+                            .char_indices()
+                            .nth(char_idx)
+                            .map(|(i, _)| i)
+                            .unwrap_or_else(|| line.len());
+                        line.insert(byte_idx, c);
+                        self.cursor_position += 1;
+                    }
+                    KeyCode::PageUp => {
+                        self.scroll_by(-(self.page_height.max(1) as isize));
+                    }
+                    KeyCode::PageDown => {
+                        self.scroll_by(self.page_height.max(1) as isize);
+                    }
+                    KeyCode::Home if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        let room = self.view();
+                        room.follow = false;
+                        room.scroll_offset = 0;
+                    }
+                    KeyCode::End if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.view().scroll_to_bottom();
+                    }
+                    KeyCode::Home => {
+                        let (line_idx, _) = self.get_cursor_line_char_index();
+                        self.cursor_position = self.get_line_start(line_idx);
+                    }
+                    KeyCode::End => {
+                        let (line_idx, _) = self.get_cursor_line_char_index();
+                        self.cursor_position = self.get_line_end(line_idx);
+                    }
+                    _ => {}
+                }
+                if self.active_room == room_before && self.new_message_text != text_before {
+                    self.input_changed();
                 }
             }
 
-            terminal.draw(|frame| self.draw(frame))?;
+            let status = self.status_snapshot();
+            if self.dirty || status != self.drawn_status {
+                terminal.draw(|frame| self.draw(frame))?;
+                self.dirty = false;
+                self.drawn_status = status;
+            }
 
             if self.exit {
                 break;
@@ -313,25 +1062,834 @@ impl App {
         Ok(())
     }
 
+    fn status_snapshot(&mut self) -> StatusSnapshot {
+        StatusSnapshot {
+            status: self.client.status().to_string(),
+            pending: self.client.pending().len(),
+            typists: self
+                .typists
+                .typists(&self.rooms[self.active_room].name, Instant::now()),
+        }
+    }
+
     fn send_message(&mut self) {
         let message = self.new_message_text.join("\n");
-        if !message.trim().is_empty() {
-            self.input_history.push(message.clone());
-            self.history_index = self.input_history.len();
+        self.new_message_text = vec!["".to_string()];
+        self.cursor_position = 0;
+        if message.trim().is_empty() {
+            return;
+        }
 
-            let json_message = serde_json::json!({
-                "nick": self.nick,
-                "message": message,
-            });
-            let json_string = json_message.to_string();
+        if let Err(e) = self.input_history.add(&message) {
+            self.show_error(format!("Could not save input history: {}", e));
+        }
+
+        match command::parse(&message) {
+            Ok(Input::Text(text)) => self.send_to_active(&text, false),
+            Ok(Input::Command(command)) => self.run_command(command),
+            Err(error) => self.show_error(error),
+        }
+    }
+
+    fn run_command(&mut self, command: SlashCommand) {
+        match command {
+            SlashCommand::Nick(nick) => {
+                self.client.set_nick(&nick);
+                if self.client.supports_rooms() {
+                    self.pending_nick = Some(nick);
+                } else {
+                    // Legacy servers never confirm the change.
+                    self.save_nick(nick);
+                }
+            }
+            SlashCommand::Join(room) => self.join_room(&room),
+            SlashCommand::Part => self.part_room(),
+            SlashCommand::Msg { nick, text } => {
+                let index = self.direct_tab(&nick);
+                self.switch_room(index);
+                if !text.trim().is_empty() {
+                    self.send_to_active(&text, false);
+                }
+            }
+            SlashCommand::Me(text) => self.send_to_active(&text, true),
+            SlashCommand::Away => {
+                if self.client.presence() == Presence::Away {
+                    self.client.set_presence(Presence::Active);
+                    self.show_hint("You are no longer marked as away".to_string());
+                } else {
+                    self.client.set_presence(Presence::Away);
+                    self.show_hint("You are now marked as away".to_string());
+                }
+            }
+            SlashCommand::Clear => {
+                let room = &mut self.rooms[self.active_room];
+                room.messages.clear();
+                room.lines.clear();
+                room.server_has_older = false;
+                room.scroll_to_bottom();
+            }
+            SlashCommand::Export { format, path } => self.export(format, path),
+            SlashCommand::Quit => self.exit = true,
+            SlashCommand::Help(None) => {
+                for spec in COMMANDS {
+                    self.local_notice(format!("{:<20} {}", spec.usage, spec.help));
+                }
+                self.local_notice("Start a message with // to send a leading /".to_string());
+            }
+            SlashCommand::Help(Some(name)) => {
+                if let Some(spec) = command::spec(&name) {
+                    self.local_notice(format!("{} - {}", spec.usage, spec.help));
+                }
+            }
+            SlashCommand::Reconnect => self.client.reconnect(),
+        }
+    }
 
-            if let Err(e) = self.ws_tx.send(json_string) {
-                *self.connection_status.lock().unwrap() = format!("Send error: {}", e);
+    // Completes the command name being typed. Returns false when the input
+    // is not a command name, so Tab keeps its Tab+Enter meaning.
+    fn complete_command(&mut self) -> bool {
+        let [line] = self.new_message_text.as_slice() else {
+            return false;
+        };
+        let Some(prefix) = line.strip_prefix('/') else {
+            return false;
+        };
+        if prefix.starts_with('/')
+            || prefix.contains(' ')
+            || self.cursor_position != line.chars().count()
+        {
+            return false;
+        }
+
+        let names = command::complete(prefix);
+        let completed = match names.as_slice() {
+            [] => {
+                self.show_error(format!("No command starts with /{}", prefix));
+                return true;
+            }
+            [name] => format!("/{} ", name),
+            _ => {
+                self.show_hint(
+                    names
+                        .iter()
+                        .map(|name| format!("/{}", name))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+                let common = names.iter().skip(1).fold(names[0], |common, name| {
+                    let len = common
+                        .chars()
+                        .zip(name.chars())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    &common[..len]
+                });
+                format!("/{}", common)
             }
+        };
+        self.cursor_position = completed.chars().count();
+        self.new_message_text = vec![completed];
+        true
+    }
+
+    fn send_to_active(&mut self, text: &str, action: bool) {
+        let room = &mut self.rooms[self.active_room];
+        room.scroll_to_bottom();
+        let sent = match (room.direct_peer(), action) {
+            (Some(nick), false) => self.client.send_direct(nick, text),
+            (Some(nick), true) => self.client.send_direct(nick, &format!("* {}", text)),
+            (None, false) => {
+                self.client.send_to(&room.name, text);
+                true
+            }
+            (None, true) => {
+                self.client.send_action(&room.name, text);
+                true
+            }
+        };
+        if !sent {
+            self.show_error("This server does not support private messages".to_string());
         }
+    }
 
-        self.new_message_text = vec!["".to_string()];
-        self.cursor_position = 0;
+    // Index of the tab for the conversation with `nick`, opening one if
+    // needed.
+    fn direct_tab(&mut self, nick: &str) -> usize {
+        let name = format!("@{}", nick);
+        match self.rooms.iter().position(|room| room.name == name) {
+            Some(index) => index,
+            None => {
+                let room = self.new_room(&name);
+                self.rooms.push(room);
+                self.rooms.len() - 1
+            }
+        }
+    }
+
+    // Files a received frame under the room it belongs to. Frames that are
+    // not tied to a room show up in the active one.
+    fn route_incoming(&mut self, incoming: Incoming) {
+        if let Incoming::Event(event) = &incoming {
+            self.update_members(event);
+            match event {
+                ServerEvent::Typing { room, nick, typing } => {
+                    self.typists.update(room, nick, *typing, Instant::now());
+                }
+                // A message ends the typing that led to it.
+                ServerEvent::Chat { room, nick, .. } => {
+                    self.typists.update(room, nick, false, Instant::now());
+                }
+                // A page asked for before reconnecting, or refused, is not
+                // coming, so scrolling to the top asks again. Errors do not
+                // say which room they are about; a page arriving twice is
+                // filtered out by id anyway.
+                ServerEvent::Welcome { .. } | ServerEvent::Error { .. } => {
+                    for room in &mut self.rooms {
+                        room.loading_older = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let theme = self.theme();
+        let index = match &incoming {
+            Incoming::Event(
+                ServerEvent::Welcome { .. }
+                | ServerEvent::Roster { .. }
+                | ServerEvent::PresenceChange { .. }
+                | ServerEvent::Typing { .. },
+            ) => return,
+            Incoming::Event(
+                ServerEvent::Chat { room, .. }
+                | ServerEvent::Join { room, .. }
+                | ServerEvent::Leave { room, .. },
+            ) => match self.rooms.iter().position(|joined| joined.name == *room) {
+                Some(index) => index,
+                None => return,
+            },
+            Incoming::Event(ServerEvent::History {
+                room,
+                messages,
+                more,
+            }) => {
+                if let Some(room) = self.rooms.iter_mut().find(|joined| joined.name == *room) {
+                    room.add_history(messages, *more, &theme);
+                }
+                return;
+            }
+            Incoming::Event(ServerEvent::NickChange {
+                old_nick, new_nick, ..
+            }) => {
+                if self.pending_nick.as_ref() == Some(new_nick) {
+                    self.pending_nick = None;
+                    self.save_nick(new_nick.clone());
+                }
+                // Only where the nick is seen: rooms it is in, which
+                // `update_members` already renamed, and the conversation
+                // with it.
+                for index in 0..self.rooms.len() {
+                    let room = &self.rooms[index];
+                    if room.members.contains(new_nick) || room.direct_peer() == Some(old_nick) {
+                        self.log_message(index, &incoming);
+                        self.rooms[index].push(incoming.clone(), &theme);
+                    }
+                }
+                return;
+            }
+            Incoming::Event(ServerEvent::Direct { from, to, .. }) => {
+                let peer = if *from == self.client.nick() {
+                    to
+                } else {
+                    from
+                };
+                self.direct_tab(peer)
+            }
+            Incoming::Event(ServerEvent::NickRejected { nick, reason }) => {
+                if self.pending_nick.as_ref() == Some(nick) {
+                    self.pending_nick = None;
+                }
+                self.show_error(format!("Cannot use nickname '{}': {}", nick, reason));
+                self.active_room
+            }
+            // Legacy servers only have the one room.
+            Incoming::Legacy(_) => 0,
+            _ => self.active_room,
+        };
+
+        let is_chat = matches!(
+            incoming,
+            Incoming::Event(ServerEvent::Chat { .. } | ServerEvent::Direct { .. })
+        );
+        self.log_message(index, &incoming);
+        self.collect_mention(&incoming, &theme);
+        let room = &mut self.rooms[index];
+        room.push(incoming, &theme);
+        if is_chat && index != self.active_room {
+            room.unread += 1;
+        }
+    }
+
+    // Messages from others that name us or a highlight word, and direct
+    // messages to us, go to the mentions view as well, and are announced
+    // while the terminal is in the background.
+    fn collect_mention(&mut self, incoming: &Incoming, theme: &Theme) {
+        let (title, text) = match incoming {
+            Incoming::Event(ServerEvent::Chat {
+                room, nick, text, ..
+            }) if theme.is_highlight(nick, text) => (format!("{} in #{}", nick, room), text),
+            Incoming::Event(ServerEvent::Direct { from, text, .. }) if *from != theme.own_nick => {
+                (from.clone(), text)
+            }
+            _ => return,
+        };
+        if !self.focused {
+            self.unfocused_mentions += 1;
+            self.alerts.push(highlight::notification(&title, text));
+        }
+        self.mentions.push(incoming.clone(), theme);
+        if !self.show_mentions {
+            self.mentions.unread += 1;
+        }
+    }
+
+    // Writes pending notifications, and the window title with the number of
+    // mentions missed while unfocused.
+    fn write_alerts(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let title = match self.unfocused_mentions {
+            0 => "termchat".to_string(),
+            count => format!("({}) termchat", count),
+        };
+        if title == self.window_title && self.alerts.is_empty() {
+            return Ok(());
+        }
+        for alert in self.alerts.drain(..) {
+            out.write_all(alert.as_bytes())?;
+        }
+        execute!(out, SetTitle(&title))?;
+        self.window_title = title;
+        Ok(())
+    }
+
+    // Moves the message view by `lines`; negative is up. Scrolling back down
+    // to the end pins the view again on the next draw. Scrolling above the
+    // oldest message reads older ones back from disk, or asks the server.
+    fn scroll_by(&mut self, lines: isize) {
+        let theme = self.theme();
+        let room = self.view();
+        room.follow = false;
+        let mut loaded = Ok(0);
+        let mut ask_server = None;
+        if lines < 0 && room.scroll_offset < lines.unsigned_abs() {
+            if room.messages.has_older() {
+                loaded = room.load_older(&theme);
+            } else if room.server_has_older && !room.loading_older {
+                room.loading_older = true;
+                ask_server = Some((room.name.clone(), room.oldest_id));
+            }
+        }
+        room.scroll_offset = room.scroll_offset.saturating_add_signed(lines);
+        if let Some((name, before)) = ask_server {
+            self.client.load_older(&name, before, LOAD_OLDER_PAGE);
+        }
+        if let Err(e) = loaded {
+            self.show_error(format!("Could not read older messages: {}", e));
+        }
+    }
+
+    fn start_search(&mut self) {
+        match self.search_corpus() {
+            Ok(corpus) => self.search = Some(Search::new(corpus)),
+            Err(e) => self.show_error(format!("Could not read older messages: {}", e)),
+        }
+    }
+
+    // Keys while searching edit the query and step through the hits.
+    fn search_key(&mut self, key_event: KeyEvent) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        match key_event.code {
+            KeyCode::Esc => {
+                self.search = None;
+                self.search_logs = None;
+                return;
+            }
+            KeyCode::Enter | KeyCode::Up => {
+                search.step(-1);
+            }
+            KeyCode::Down => {
+                search.step(1);
+            }
+            KeyCode::Char('r') if control => search.toggle_regex(),
+            KeyCode::Char('l') if control => {
+                self.toggle_search_logs();
+            }
+            KeyCode::Backspace => search.pop(),
+            KeyCode::Char(c) if !control => search.push(c),
+            KeyCode::PageUp => {
+                self.scroll_by(-(self.page_height.max(1) as isize));
+                return;
+            }
+            KeyCode::PageDown => {
+                self.scroll_by(self.page_height.max(1) as isize);
+                return;
+            }
+            _ => return,
+        }
+        if let Some(index) = self.search.as_ref().and_then(Search::current) {
+            self.show_message(index);
+        }
+    }
+
+    // Switches the search between the messages on screen and the logs of
+    // the active tab.
+    fn toggle_search_logs(&mut self) {
+        if self.search_logs.take().is_none() {
+            let Some(transcript) = &self.transcript else {
+                self.show_hint("Searching logs needs --log-dir".to_string());
+                return;
+            };
+            let name = self.rooms[self.active_room].name.clone();
+            let entries = match transcript.read(&name, None, None) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.show_error(format!("Could not read logs: {}", e));
+                    return;
+                }
+            };
+            let theme = self.theme();
+            let mut logs = Room::new("", History::new(usize::MAX, None));
+            for entry in &entries {
+                logs.push(entry.to_incoming(&name), &theme);
+            }
+            self.search_logs = Some(logs);
+        }
+        match self.search_corpus() {
+            Ok(corpus) => {
+                if let Some(search) = &mut self.search {
+                    search.set_corpus(corpus);
+                }
+            }
+            Err(e) => self.show_error(format!("Could not read older messages: {}", e)),
+        }
+    }
+
+    // The text of every message of the view, paged out ones included, as
+    // it is drawn.
+    fn search_corpus(&mut self) -> io::Result<Vec<String>> {
+        let theme = self.theme();
+        let room = self.view();
+        let corpus = room
+            .messages
+            .all()?
+            .iter()
+            .filter_map(|incoming| render_incoming(incoming, &theme, room.labelled))
+            .map(|(prefix, text, _)| {
+                let mut line: String = prefix.into_iter().map(|(part, _)| part).collect();
+                line.push_str(&text);
+                line
+            })
+            .collect();
+        Ok(corpus)
+    }
+
+    // Scrolls message `index` of the view's history, as `History::all`
+    // returned it, into the upper part of the pane, reading it back from
+    // disk first if it was paged out.
+    fn show_message(&mut self, index: usize) {
+        let theme = self.theme();
+        let height = self.page_height;
+        let room = self.view();
+        let index = index + room.messages.first_kept();
+        while room.messages.paged_out() > index {
+            match room.load_older(&theme) {
+                Ok(0) => {
+                    self.show_error("Message is no longer available".to_string());
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.show_error(format!("Could not read older messages: {}", e));
+                    return;
+                }
+            }
+        }
+        let Some(row) = room.lines.message_row(index - room.messages.paged_out()) else {
+            return;
+        };
+        room.follow = false;
+        room.scroll_offset = row.saturating_sub(height / 3);
+    }
+
+    // "Search logs (regex): query│ 2/5" and the keys that go with it.
+    fn search_prompt(&self, search: &Search) -> Line<'static> {
+        let mut label = " Search".to_string();
+        if self.search_logs.is_some() {
+            label.push_str(" logs");
+        }
+        if search.is_regex() {
+            label.push_str(" (regex)");
+        }
+        let mut line = vec![
+            Span::styled(
+                format!("{}: ", label),
+                Style::default().fg(Color::LightCyan),
+            ),
+            Span::raw(format!("{}│ ", search.query())),
+        ];
+        let (result, color) = match (search.error(), search.position()) {
+            (Some(e), _) => (e.to_string(), Color::LightRed),
+            (None, Some((hit, count))) => (format!("{}/{}", hit, count), Color::Gray),
+            (None, None) if search.query().is_empty() => (String::new(), Color::Gray),
+            (None, None) => ("no matches".to_string(), Color::LightRed),
+        };
+        line.push(Span::styled(result, Style::default().fg(color)));
+        line.push(Span::styled(
+            "  Enter/↑ older, ↓ newer, Ctrl+R regex, Ctrl+L logs, Esc close",
+            Style::default().fg(Color::DarkGray),
+        ));
+        Line::from(line)
+    }
+
+    // Keys while reverse searching the input history; returns false for
+    // keys that accept the entry found and should then act as usual.
+    fn reverse_search_key(&mut self, key_event: KeyEvent) -> bool {
+        let Some(reverse) = &mut self.reverse_search else {
+            return false;
+        };
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        let cancel =
+            key_event.code == KeyCode::Esc || (control && key_event.code == KeyCode::Char('g'));
+        let before = match key_event.code {
+            _ if cancel => {
+                self.new_message_text = std::mem::take(&mut reverse.draft);
+                self.cursor_position = reverse.draft_cursor;
+                self.reverse_search = None;
+                return true;
+            }
+            KeyCode::Char('r') if control => reverse.found.unwrap_or(self.input_history.len()),
+            KeyCode::Backspace => {
+                reverse.query.pop();
+                self.input_history.len()
+            }
+            KeyCode::Char(c) if !control => {
+                reverse.query.push(c);
+                reverse
+                    .found
+                    .map_or(self.input_history.len(), |found| found + 1)
+            }
+            KeyCode::Enter => {
+                self.reverse_search = None;
+                self.send_message();
+                return true;
+            }
+            _ => {
+                self.reverse_search = None;
+                return false;
+            }
+        };
+        if reverse.query.is_empty() {
+            reverse.found = None;
+            reverse.failing = false;
+            return true;
+        }
+        match self.input_history.search(&reverse.query, before) {
+            Some(found) => {
+                reverse.found = Some(found);
+                reverse.failing = false;
+                let entry = self
+                    .input_history
+                    .get(found)
+                    .unwrap_or_default()
+                    .to_string();
+                self.set_input(&entry);
+            }
+            None => reverse.failing = true,
+        }
+        true
+    }
+
+    // "(reverse-i-search)`query': " as readline shows it.
+    fn reverse_search_prompt(reverse: &ReverseSearch) -> Line<'static> {
+        let label = if reverse.failing {
+            " (failing reverse-i-search)"
+        } else {
+            " (reverse-i-search)"
+        };
+        Line::from(vec![
+            Span::styled(
+                format!("{}`{}': ", label, reverse.query),
+                Style::default().fg(Color::LightCyan),
+            ),
+            Span::styled(
+                "Ctrl+R older, Enter send, Esc cancel, other keys edit",
+                Style::default().fg(Color::DarkGray),
+            ),
+        ])
+    }
+
+    // Scrolls the "new messages" divider to the top of the pane.
+    fn jump_to_divider(&mut self) {
+        let room = self.view();
+        match room.lines.divider_row() {
+            Some(row) => {
+                room.follow = false;
+                room.scroll_offset = row;
+            }
+            None => self.show_hint("No new messages since you were away".to_string()),
+        }
+    }
+
+    // The messages on screen: logs being searched, the mentions view or the
+    // active tab.
+    fn view(&mut self) -> &mut Room {
+        if let Some(logs) = &mut self.search_logs {
+            logs
+        } else if self.show_mentions {
+            &mut self.mentions
+        } else {
+            &mut self.rooms[self.active_room]
+        }
+    }
+
+    fn theme(&self) -> Theme {
+        Theme {
+            depth: self.colors,
+            own_nick: self.client.nick(),
+            highlighter: Rc::clone(&self.highlighter),
+        }
+    }
+
+    // A tab whose history beyond `history_limit` is paged out to a file of
+    // its own, or dropped if that file cannot be created.
+    fn new_room(&mut self, name: &str) -> Room {
+        self.spill_files += 1;
+        let path =
+            self.spill_dir
+                .join(format!("{}-{}.jsonl", std::process::id(), self.spill_files));
+        let history = History::new(self.history_limit, SpillFile::create(&path).ok());
+        Room::new(name, history)
+    }
+
+    // Announces typing while a message is being written. Commands and
+    // direct conversations are not announced.
+    fn input_changed(&mut self) {
+        let first_line = &self.new_message_text[0];
+        let is_command = first_line.starts_with('/') && !first_line.starts_with("//");
+        if self.get_total_chars() == 0 || is_command {
+            self.stop_typing();
+        } else if self.typing.edited(Instant::now()) {
+            self.announce_typing(true);
+        }
+    }
+
+    fn stop_typing(&mut self) {
+        if self.typing.stop() {
+            self.announce_typing(false);
+        }
+    }
+
+    fn announce_typing(&self, typing: bool) {
+        let room = &self.rooms[self.active_room];
+        if room.direct_peer().is_none() {
+            self.client.set_typing(&room.name, typing);
+        }
+    }
+
+    // Keeps the per-room member lists and everyone's presence in sync with
+    // the server.
+    fn update_members(&mut self, event: &ServerEvent) {
+        match event {
+            ServerEvent::Roster { room, members } => {
+                if let Some(room) = self.rooms.iter_mut().find(|joined| joined.name == *room) {
+                    room.members = members.iter().map(|member| member.nick.clone()).collect();
+                }
+                for member in members {
+                    self.presence.insert(member.nick.clone(), member.presence);
+                }
+            }
+            ServerEvent::Join { room, nick, .. } => {
+                if let Some(room) = self.rooms.iter_mut().find(|joined| joined.name == *room) {
+                    room.members.insert(nick.clone());
+                }
+                self.presence.entry(nick.clone()).or_default();
+            }
+            ServerEvent::Leave { room, nick, .. } => {
+                if let Some(room) = self.rooms.iter_mut().find(|joined| joined.name == *room) {
+                    room.members.remove(nick);
+                }
+                if !self.rooms.iter().any(|room| room.members.contains(nick)) {
+                    self.presence.remove(nick);
+                }
+            }
+            ServerEvent::NickChange {
+                old_nick, new_nick, ..
+            } => {
+                for room in &mut self.rooms {
+                    if room.members.remove(old_nick) {
+                        room.members.insert(new_nick.clone());
+                    }
+                }
+                if let Some(presence) = self.presence.remove(old_nick) {
+                    self.presence.insert(new_nick.clone(), presence);
+                }
+            }
+            ServerEvent::PresenceChange { nick, presence } => {
+                self.presence.insert(nick.clone(), *presence);
+            }
+            _ => {}
+        }
+    }
+
+    // Makes `index` the active tab, parking the editor contents in the room
+    // being left and restoring the draft of the room being entered.
+    fn switch_room(&mut self, index: usize) {
+        if index >= self.rooms.len() {
+            return;
+        }
+        self.show_mentions = false;
+        if index == self.active_room {
+            return;
+        }
+        self.stop_typing();
+
+        let old = &mut self.rooms[self.active_room];
+        std::mem::swap(&mut old.draft, &mut self.new_message_text);
+        old.draft_cursor = self.cursor_position;
+
+        self.active_room = index;
+        let new = &mut self.rooms[index];
+        self.new_message_text = std::mem::replace(&mut new.draft, vec!["".to_string()]);
+        self.cursor_position = new.draft_cursor;
+        new.unread = 0;
+    }
+
+    fn join_room(&mut self, name: &str) {
+        if let Some(index) = self.rooms.iter().position(|room| room.name == name) {
+            self.switch_room(index);
+            return;
+        }
+        if self.client.is_legacy() {
+            self.show_error("This server does not support rooms".to_string());
+            return;
+        }
+
+        self.client.join(name);
+        let room = self.new_room(name);
+        self.rooms.push(room);
+        self.switch_room(self.rooms.len() - 1);
+    }
+
+    fn part_room(&mut self) {
+        if self.active_room == 0 {
+            self.show_error(format!("You cannot leave #{}", DEFAULT_ROOM));
+            return;
+        }
+
+        // Closing a direct conversation only closes the tab.
+        self.stop_typing();
+        let room = self.rooms.remove(self.active_room);
+        if room.direct_peer().is_none() {
+            self.client.part(&room.name);
+        }
+        self.active_room -= 1;
+        let new = &mut self.rooms[self.active_room];
+        self.new_message_text = std::mem::replace(&mut new.draft, vec!["".to_string()]);
+        self.cursor_position = new.draft_cursor;
+        new.unread = 0;
+    }
+
+    fn save_nick(&mut self, nick: String) {
+        self.config.nick = Some(nick);
+        let Some(path) = &self.config_path else {
+            return;
+        };
+        if let Err(e) = self.config.save(path) {
+            self.show_error(format!("Could not save {}: {}", path.display(), e));
+        }
+    }
+
+    // Shows a line that only exists locally, such as /help output.
+    fn local_notice(&mut self, text: String) {
+        let theme = self.theme();
+        let room = &mut self.rooms[self.active_room];
+        room.push(
+            Incoming::Event(ServerEvent::System {
+                timestamp: Utc::now(),
+                text,
+            }),
+            &theme,
+        );
+        room.scroll_to_bottom();
+    }
+
+    // Command feedback is shown in the status bar until the next key press.
+    fn show_error(&mut self, message: String) {
+        self.status_note = Some((message, Color::LightRed));
+    }
+
+    // Logging is best effort: a failing log is reported once and switched
+    // off rather than getting in the way of chatting.
+    fn log_message(&mut self, room: usize, incoming: &Incoming) {
+        let Some(log) = &self.transcript else {
+            return;
+        };
+        let Some(entry) = LogEntry::from_incoming(incoming, Utc::now()) else {
+            return;
+        };
+        if let Err(e) = log.append(&self.rooms[room].name, &entry) {
+            self.transcript = None;
+            self.show_error(format!("Logging stopped: {}", e));
+        }
+    }
+
+    // Writes everything in the current tab, including messages paged out to
+    // disk, to `path` or to "<room>-<time>.<format>".
+    fn export(&mut self, format: ExportFormat, path: Option<String>) {
+        let room = &mut self.rooms[self.active_room];
+        let now = Utc::now();
+        let exported = room.messages.all().and_then(|messages| {
+            let entries: Vec<LogEntry> = messages
+                .iter()
+                .filter_map(|message| LogEntry::from_incoming(message, now))
+                .collect();
+            let path = path.map(PathBuf::from).unwrap_or_else(|| {
+                let time = Local::now().format("%Y%m%d-%H%M%S");
+                let name = transcript::safe_file_name(&room.name);
+                PathBuf::from(format!("{}-{}.{}", name, time, format.extension()))
+            });
+            fs::write(&path, export::export(&room.name, &entries, format))?;
+            Ok((entries.len(), path))
+        });
+        match exported {
+            Ok((count, path)) => {
+                self.show_hint(format!("Exported {} messages to {}", count, path.display()))
+            }
+            Err(e) => self.show_error(format!("Export failed: {}", e)),
+        }
+    }
+
+    fn show_hint(&mut self, message: String) {
+        self.status_note = Some((message, Color::Gray));
+    }
+
+    fn cancel_pending_message(&mut self) {
+        let Some(entry) = self.client.cancel_last_pending() else {
+            return;
+        };
+
+        if self.get_total_chars() == 0 {
+            self.new_message_text = entry.text.split('\n').map(str::to_string).collect();
+            self.cursor_position = self.get_total_chars();
+        }
+    }
+
+    // Replaces the text being edited, putting the cursor at its end.
+    fn set_input(&mut self, text: &str) {
+        self.new_message_text = text.split('\n').map(str::to_string).collect();
+        self.cursor_position = self.get_total_chars();
     }
 
     fn get_total_chars(&self) -> usize {
@@ -380,9 +1938,48 @@ impl App {
         (last_idx, self.new_message_text[last_idx].chars().count())
     }
 
+    fn draw_users(&self, frame: &mut Frame, area: Rect) {
+        let me = self.client.nick();
+        let room = &self.rooms[self.active_room];
+        let mut members: Vec<(&String, Presence)> = room
+            .members
+            .iter()
+            .map(|nick| (nick, self.presence.get(nick).copied().unwrap_or_default()))
+            .collect();
+        members.sort_by_key(|(nick, presence)| (*presence, nick.to_lowercase()));
+
+        let lines: Vec<Line> = members
+            .into_iter()
+            .map(|(nick, presence)| {
+                let (marker, color) = match presence {
+                    Presence::Active => ("●", Color::Green),
+                    Presence::Idle => ("◐", Color::Yellow),
+                    Presence::Away => ("○", Color::DarkGray),
+                };
+                let mut nick_style = Style::default().fg(color);
+                if *nick == me {
+                    nick_style = nick_style.add_modifier(Modifier::BOLD);
+                }
+                Line::from(vec![
+                    Span::styled(format!(" {} ", marker), Style::default().fg(color)),
+                    Span::styled(nick.clone(), nick_style),
+                ])
+            })
+            .collect();
+
+        let block = Block::default()
+            .borders(Borders::LEFT)
+            .title(format!(" Users ({}) ", room.members.len()));
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
     fn draw(&mut self, frame: &mut Frame) {
+        let typists = self
+            .typists
+            .typists(&self.rooms[self.active_room].name, Instant::now());
         let vertical_layout = Layout::vertical([
             Constraint::Length(3),                                        // Title bar
+            Constraint::Length(1),                                        // Room tabs
             Constraint::Min(3),                                           // Messages
             Constraint::Length(2),                                        // Status bar
             Constraint::Length(1),                                        // Input title
@@ -391,11 +1988,21 @@ impl App {
 
         let [
             title_area,
+            tabs_area,
             messages_area,
             status_area,
             input_title_area,
             input_area,
         ] = vertical_layout.areas(frame.area());
+        let messages_area = if self.show_users {
+            let [messages_area, users_area] =
+                Layout::horizontal([Constraint::Min(20), Constraint::Length(24)])
+                    .areas(messages_area);
+            self.draw_users(frame, users_area);
+            messages_area
+        } else {
+            messages_area
+        };
 
         let title = Block::default()
             .title(" 💬 Rust Chat Client ")
@@ -405,60 +2012,148 @@ impl App {
             .style(Style::default().bg(Color::DarkGray));
         frame.render_widget(title, title_area);
 
-        let messages = self.messages.lock().unwrap();
-
-        let mut all_lines = Vec::new();
-        for msg in messages.iter() {
-            let lines: Vec<&str> = msg.split('\n').collect();
-            if !lines.is_empty() {
-                for line in lines.iter() {
-                    all_lines.push(line.to_string());
-                }
-            }
+        let mut tab_titles: Vec<Line> = self.rooms.iter().map(Room::tab_title).collect();
+        if self.show_mentions || self.mentions.unread > 0 {
+            tab_titles.push(self.mentions.tab_title());
         }
+        let selected = if self.show_mentions {
+            self.rooms.len()
+        } else {
+            self.active_room
+        };
+        let tabs = Tabs::new(tab_titles)
+            .select(selected)
+            .style(Style::default().fg(Color::Gray))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::LightCyan)
+                    .add_modifier(Modifier::BOLD),
+            );
+        frame.render_widget(tabs, tabs_area);
 
-        if self.scroll_to_bottom {
-            self.scroll_offset = all_lines
-                .len()
-                .saturating_sub(messages_area.height as usize);
-            self.scroll_to_bottom = false;
-        }
+        // Scrolling counts wrapped rows. Received messages are laid out once
+        // and again only on resize; the few pending ones on every draw.
+        let width = messages_area.width as usize;
+        let height = messages_area.height as usize;
+        self.page_height = height;
+        let pending_style = Style::default()
+            .fg(Color::DarkGray)
+            .add_modifier(Modifier::ITALIC);
+        let prefix = format!("[pending] {}: ", self.client.nick());
+        let view_name = self.view().name.clone();
+        let pending_rows: Vec<String> = self
+            .client
+            .pending()
+            .entries()
+            .filter(|entry| entry.room == view_name)
+            .flat_map(|entry| layout::wrap_message(&prefix, &entry.text, width))
+            .collect();
+        let search_pattern = self
+            .search
+            .as_ref()
+            .and_then(|search| search.pattern().cloned());
+        let match_style = |style: &Style| style.fg(Color::Black).bg(Color::LightYellow);
 
-        let max_scroll = all_lines
-            .len()
-            .saturating_sub(messages_area.height as usize);
-        if self.scroll_offset > max_scroll {
-            self.scroll_offset = max_scroll;
+        let room = self.view();
+        room.lines.set_width(width);
+        let max_scroll = (room.lines.row_count() + pending_rows.len()).saturating_sub(height);
+        if room.follow || room.scroll_offset >= max_scroll {
+            // Going back to the bottom may page old messages out.
+            room.scroll_to_bottom();
+            room.scroll_offset =
+                (room.lines.row_count() + pending_rows.len()).saturating_sub(height);
         }
+        let received_rows = room.lines.row_count();
 
-        let visible_lines: Vec<&str> = all_lines
-            .iter()
-            .skip(self.scroll_offset)
-            .take(messages_area.height as usize)
-            .map(|s| s.as_str())
+        // While scrolled up, the last row tells how much is below.
+        let view_height = if room.follow {
+            height
+        } else {
+            height.saturating_sub(1)
+        };
+        let mut visible_lines: Vec<Line> = room
+            .lines
+            .rows(room.scroll_offset, view_height)
+            .map(|row| {
+                let row = match &search_pattern {
+                    Some(pattern) => search::mark_matches(row, pattern, match_style),
+                    None => row.to_vec(),
+                };
+                Line::from(
+                    row.into_iter()
+                        .map(|(text, style)| Span::styled(text, style))
+                        .collect::<Vec<_>>(),
+                )
+            })
             .collect();
-
-        let messages_text: Text = Text::from(
-            visible_lines
-                .iter()
-                .map(|line| Line::from(Span::raw(*line)))
-                .collect::<Vec<_>>(),
+        let shown = visible_lines.len();
+        visible_lines.extend(
+            pending_rows
+                .into_iter()
+                .skip((room.scroll_offset + shown).saturating_sub(received_rows))
+                .take(view_height - shown)
+                .map(|row| Line::from(Span::styled(row, pending_style))),
         );
+        if !room.follow {
+            visible_lines.resize(view_height, Line::default());
+            let more = if room.unseen > 0 {
+                format!(" ▼ more below ({} new) - Ctrl+End to jump ", room.unseen)
+            } else {
+                " ▼ more below - Ctrl+End to jump ".to_string()
+            };
+            visible_lines.push(Line::from(Span::styled(
+                more,
+                Style::default().fg(Color::Black).bg(Color::Yellow),
+            )));
+        }
+
+        let messages_text: Text = Text::from(visible_lines);
 
         let msg_widget =
             Paragraph::new(messages_text).block(Block::default().borders(Borders::NONE));
 
         frame.render_widget(msg_widget, messages_area);
 
-        let status = self.connection_status.lock().unwrap();
-        let status_widget = Paragraph::new(status.as_str())
-            .block(Block::default().borders(Borders::TOP))
-            .style(Style::default().fg(Color::Yellow));
+        let outbox = self.client.pending();
+        let status = self.client.status();
+        let status_color = match status.state {
+            ConnectionState::Connected => Color::Green,
+            ConnectionState::Connecting => Color::Yellow,
+            ConnectionState::Backoff { .. } | ConnectionState::Disconnected => Color::Red,
+        };
+        let mut status_text = status.to_string();
+        if !outbox.is_empty() {
+            status_text.push_str(&format!(
+                " | {} pending (Ctrl+X to cancel last)",
+                outbox.len()
+            ));
+        }
+        let mut status_line = vec![Span::styled(status_text, Style::default().fg(status_color))];
+        if let Some((note, color)) = &self.status_note {
+            status_line.push(Span::raw(" | "));
+            status_line.push(Span::styled(note.clone(), Style::default().fg(*color)));
+        }
+        if let Some(typing) = typing::describe(&typists) {
+            status_line.push(Span::raw(" | "));
+            status_line.push(Span::styled(
+                typing,
+                Style::default()
+                    .fg(Color::Gray)
+                    .add_modifier(Modifier::ITALIC),
+            ));
+        }
+        let status_widget =
+            Paragraph::new(Line::from(status_line)).block(Block::default().borders(Borders::TOP));
         frame.render_widget(status_widget, status_area);
 
-        let input_title =
-            Paragraph::new(" Type your message (Enter to send, Tab+Enter for new line):")
-                .style(Style::default().fg(Color::LightCyan));
+        let input_title = match (&self.search, &self.reverse_search) {
+            (Some(search), _) => Paragraph::new(self.search_prompt(search)),
+            (None, Some(reverse)) => Paragraph::new(Self::reverse_search_prompt(reverse)),
+            (None, None) => {
+                Paragraph::new(" Type your message (Enter to send, Tab+Enter for new line):")
+                    .style(Style::default().fg(Color::LightCyan))
+            }
+        };
         frame.render_widget(input_title, input_title_area);
 
         let input_block = Block::default()
@@ -487,7 +2182,7 @@ impl App {
                         .unwrap_or_else(|| line.len());
 
                     let mut new_line = line.clone();
-                    new_line.insert_str(byte_idx, "│");
+                    new_line.insert(byte_idx, '│');
                     result[line_idx] = new_line;
                     return result;
                 }
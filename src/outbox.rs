@@ -0,0 +1,159 @@
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Value, json};
+
+use crate::protocol::DEFAULT_ROOM;
+
+// A message that has been submitted but not yet written to the socket. Only
+// what the user typed is kept; the frame is built when it is sent, for
+// whichever server is connected by then. Direct messages are filed under the
+// `@nick` conversation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub room: String,
+    pub text: String,
+    pub action: bool,
+}
+
+// Ordered queue of unsent messages, optionally mirrored to a JSON file so a
+// crash does not lose them.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    entries: VecDeque<OutboxEntry>,
+    next_id: u64,
+    path: Option<PathBuf>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut outbox = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(outbox),
+            Err(e) => return Err(e),
+        };
+
+        let value: Value = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for item in value.as_array().into_iter().flatten() {
+            if let Some(text) = item["text"].as_str() {
+                let id = outbox.next_id;
+                outbox.next_id += 1;
+                let room = item["room"].as_str().unwrap_or(DEFAULT_ROOM);
+                outbox.entries.push_back(OutboxEntry {
+                    id,
+                    room: room.to_string(),
+                    text: text.to_string(),
+                    action: item["action"].as_bool().unwrap_or(false),
+                });
+            }
+        }
+
+        Ok(outbox)
+    }
+
+    pub fn push(&mut self, room: &str, text: String, action: bool) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push_back(OutboxEntry {
+            id,
+            room: room.to_string(),
+            text,
+            action,
+        });
+        self.save();
+        id
+    }
+
+    pub fn front(&self) -> Option<&OutboxEntry> {
+        self.entries.front()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<OutboxEntry> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        let entry = self.entries.remove(index);
+        self.save();
+        entry
+    }
+
+    pub fn cancel_last(&mut self) -> Option<OutboxEntry> {
+        let entry = self.entries.pop_back();
+        if entry.is_some() {
+            self.save();
+        }
+        entry
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Persisting is best effort: failing to write the file must never stop
+    // the message itself from being queued or sent.
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let items: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| json!({ "room": entry.room, "text": entry.text, "action": entry.action }))
+            .collect();
+        let _ = fs::write(path, Value::Array(items).to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_survives_reload() {
+        let path = std::env::temp_dir().join(format!("outbox-test-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut outbox = Outbox::load(&path).unwrap();
+        outbox.push("general", "one".to_string(), false);
+        let id = outbox.push("rust", "two".to_string(), false);
+        outbox.push("rust", "three".to_string(), true);
+        outbox.remove(id);
+
+        let reloaded = Outbox::load(&path).unwrap();
+        let texts: Vec<&str> = reloaded
+            .entries()
+            .map(|entry| entry.text.as_str())
+            .collect();
+        assert_eq!(texts, ["one", "three"]);
+        let rooms: Vec<&str> = reloaded
+            .entries()
+            .map(|entry| entry.room.as_str())
+            .collect();
+        assert_eq!(rooms, ["general", "rust"]);
+        let actions: Vec<bool> = reloaded.entries().map(|entry| entry.action).collect();
+        assert_eq!(actions, [false, true]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use std::str::FromStr;
+
+use ratatui::style::Color;
+
+// The basic colours that read well on a dark background; black, white and
+// the greys are left for text and system notices.
+const BASIC_PALETTE: &[Color] = &[
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+];
+
+// Entries of the xterm 256 colour cube bright enough for a dark background,
+// spread around the colour wheel.
+const INDEXED_PALETTE: &[u8] = &[
+    33, 39, 44, 49, 41, 77, 112, 148, 184, 178, 172, 208, 203, 168, 162, 170, 135, 99, 105, 75, 81,
+    117, 150, 222,
+];
+
+// How many colours the terminal can show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorDepth {
+    #[default]
+    Basic,
+    Indexed,
+    TrueColor,
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16" => Ok(ColorDepth::Basic),
+            "256" => Ok(ColorDepth::Indexed),
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            _ => Err(format!(
+                "Unknown colour depth '{}' (16, 256 or truecolor)",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorDepth {
+    // Reads `COLORTERM` and `TERM` the way most terminal programs do.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").ok();
+        let term = std::env::var("TERM").ok();
+        Self::from_env(colorterm.as_deref(), term.as_deref())
+    }
+
+    pub fn from_env(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            ColorDepth::TrueColor
+        } else if term.is_some_and(|term| term.contains("256color")) {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Basic
+        }
+    }
+}
+
+// A stable choice among `count` colours for `nick` (FNV-1a), so someone
+// keeps their colour across exports and sessions.
+pub fn nick_colour_index(nick: &str, count: usize) -> usize {
+    (nick_hash(nick) % count as u64) as usize
+}
+
+// The colour `nick` is drawn in. With true colour every nick gets its own
+// hue; otherwise one of a fixed palette.
+pub fn nick_colour(nick: &str, depth: ColorDepth) -> Color {
+    match depth {
+        ColorDepth::Basic => BASIC_PALETTE[nick_colour_index(nick, BASIC_PALETTE.len())],
+        ColorDepth::Indexed => {
+            Color::Indexed(INDEXED_PALETTE[nick_colour_index(nick, INDEXED_PALETTE.len())])
+        }
+        ColorDepth::TrueColor => {
+            let (r, g, b) = hsl_to_rgb((nick_hash(nick) % 360) as f64, 0.65, 0.65);
+            Color::Rgb(r, g, b)
+        }
+    }
+}
+
+// Background of messages that mention us. Every basic colour is some
+// nick's colour too, so with 16 colours the text has to go black on it.
+pub fn highlight_background(depth: ColorDepth) -> Color {
+    match depth {
+        ColorDepth::Basic => Color::Yellow,
+        ColorDepth::Indexed => Color::Indexed(58),
+        ColorDepth::TrueColor => Color::Rgb(68, 56, 20),
+    }
+}
+
+fn nick_hash(nick: &str) -> u64 {
+    nick.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let channel = |value: f64| ((value + m) * 255.0).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_follows_the_environment() {
+        assert_eq!(
+            ColorDepth::from_env(Some("truecolor"), Some("xterm-256color")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, Some("screen-256color")),
+            ColorDepth::Indexed
+        );
+        assert_eq!(ColorDepth::from_env(None, Some("xterm")), ColorDepth::Basic);
+        assert_eq!(ColorDepth::from_env(None, None), ColorDepth::Basic);
+        assert_eq!("256".parse(), Ok(ColorDepth::Indexed));
+    }
+
+    #[test]
+    fn nick_colours_are_stable_and_fit_the_depth() {
+        for depth in [
+            ColorDepth::Basic,
+            ColorDepth::Indexed,
+            ColorDepth::TrueColor,
+        ] {
+            assert_eq!(nick_colour("alice", depth), nick_colour("alice", depth));
+        }
+        assert!(BASIC_PALETTE.contains(&nick_colour("alice", ColorDepth::Basic)));
+        assert!(matches!(
+            nick_colour("alice", ColorDepth::Indexed),
+            Color::Indexed(_)
+        ));
+        assert!(matches!(
+            nick_colour("alice", ColorDepth::TrueColor),
+            Color::Rgb(..)
+        ));
+        assert_eq!(hsl_to_rgb(0.0, 1.0, 0.5), (255, 0, 0));
+        assert_eq!(hsl_to_rgb(240.0, 1.0, 0.5), (0, 0, 255));
+    }
+}
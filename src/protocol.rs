@@ -0,0 +1,405 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Bumped whenever a change would confuse older clients.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Room every client is placed in on connect, and where messages from legacy
+// clients end up.
+pub const DEFAULT_ROOM: &str = "general";
+
+const MAX_ROOM_NAME: usize = 32;
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+// A leading `@` is reserved for direct conversations, see `ClientMessage::Direct`.
+// "." and ".." are refused as they would name directories of their own.
+pub fn valid_room_name(room: &str) -> bool {
+    !room.is_empty()
+        && room != "."
+        && room != ".."
+        && !room.starts_with('@')
+        && room.chars().count() <= MAX_ROOM_NAME
+        && !room.chars().any(char::is_whitespace)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    #[default]
+    Active,
+    Idle,
+    Away,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Member {
+    pub nick: String,
+    pub presence: Presence,
+}
+
+// A chat message as stored by the server, replayed in `ServerEvent::History`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub nick: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub action: bool,
+}
+
+impl StoredMessage {
+    // The message as it was first delivered in `room`.
+    pub fn to_event(&self, room: &str) -> ServerEvent {
+        ServerEvent::Chat {
+            id: Some(self.id),
+            timestamp: self.timestamp,
+            room: room.to_string(),
+            nick: self.nick.clone(),
+            text: self.text.clone(),
+            action: self.action,
+        }
+    }
+}
+
+// Events sent from the server to clients. Every frame is a JSON object with a
+// `v` version field and a `type` tag, e.g.
+// `{"v":1,"type":"chat","timestamp":"...","nick":"alice","text":"hi"}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Welcome {
+        version: u32,
+        nick: String,
+    },
+    Chat {
+        // Position in the server's message store, increasing over time; only
+        // set by servers that keep history.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        timestamp: DateTime<Utc>,
+        #[serde(default = "default_room")]
+        room: String,
+        nick: String,
+        text: String,
+        // `/me` style message: "* nick text".
+        #[serde(default, skip_serializing_if = "is_false")]
+        action: bool,
+    },
+    System {
+        timestamp: DateTime<Utc>,
+        text: String,
+    },
+    Join {
+        timestamp: DateTime<Utc>,
+        #[serde(default = "default_room")]
+        room: String,
+        nick: String,
+    },
+    Leave {
+        timestamp: DateTime<Utc>,
+        #[serde(default = "default_room")]
+        room: String,
+        nick: String,
+    },
+    NickChange {
+        timestamp: DateTime<Utc>,
+        old_nick: String,
+        new_nick: String,
+    },
+    // Everyone in `room`, sent to a client right after it joins. Later
+    // changes arrive as `Join`, `Leave`, `NickChange` and `PresenceChange`.
+    Roster {
+        room: String,
+        members: Vec<Member>,
+    },
+    PresenceChange {
+        nick: String,
+        presence: Presence,
+    },
+    // Stored messages of `room`, oldest first: the latest ones right after
+    // joining, or a page asked for with `ClientMessage::History`. `more`
+    // says whether even older ones exist.
+    History {
+        room: String,
+        messages: Vec<StoredMessage>,
+        more: bool,
+    },
+    // Someone else in `room` started or stopped typing.
+    Typing {
+        room: String,
+        nick: String,
+        typing: bool,
+    },
+    // Sent to a client whose nick change was refused; it keeps its old nick.
+    NickRejected {
+        nick: String,
+        reason: String,
+    },
+    // A private message. Delivered to the recipient and echoed back to the
+    // sender.
+    Direct {
+        timestamp: DateTime<Utc>,
+        from: String,
+        to: String,
+        text: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// Messages sent from clients to a server that announced the structured
+// protocol with `ServerEvent::Welcome`. Older servers only understand the
+// legacy `{"nick": ..., "message": ...}` object.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    // The sender's nick on `Chat` and `Direct` is informational: the server
+    // delivers them under the nick it knows, and renames only with `Nick`.
+    Chat {
+        nick: String,
+        room: String,
+        text: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        action: bool,
+    },
+    Join {
+        room: String,
+    },
+    Part {
+        room: String,
+    },
+    Nick {
+        nick: String,
+    },
+    Presence {
+        presence: Presence,
+    },
+    Typing {
+        room: String,
+        typing: bool,
+    },
+    Direct {
+        nick: String,
+        to: String,
+        text: String,
+    },
+    // Asks for up to `limit` stored messages of a joined room from before
+    // message `before`, answered with `ServerEvent::History`.
+    History {
+        room: String,
+        before: Option<u64>,
+        limit: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    v: u32,
+    #[serde(flatten)]
+    body: T,
+}
+
+// The body of a frame in the version spoken here; frames of any other
+// version are not understood, even when they happen to look alike.
+fn from_frame<T: for<'de> Deserialize<'de>>(frame: &str) -> Option<T> {
+    serde_json::from_str::<Envelope<T>>(frame)
+        .ok()
+        .filter(|envelope| envelope.v == PROTOCOL_VERSION)
+        .map(|envelope| envelope.body)
+}
+
+// The protocol version a frame claims, if it is an envelope at all.
+pub fn frame_version(frame: &str) -> Option<u32> {
+    let value = serde_json::from_str::<serde_json::Value>(frame).ok()?;
+    value.get("v")?.as_u64()?.try_into().ok()
+}
+
+fn to_frame<T: Serialize>(body: T) -> String {
+    serde_json::to_string(&Envelope {
+        v: PROTOCOL_VERSION,
+        body,
+    })
+    .expect("protocol messages always serialize")
+}
+
+impl ClientMessage {
+    pub fn to_frame(&self) -> String {
+        to_frame(self)
+    }
+
+    pub fn parse(frame: &str) -> Option<Self> {
+        from_frame(frame)
+    }
+}
+
+impl ServerEvent {
+    pub fn to_frame(&self) -> String {
+        to_frame(self)
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            ServerEvent::Chat { timestamp, .. }
+            | ServerEvent::System { timestamp, .. }
+            | ServerEvent::Join { timestamp, .. }
+            | ServerEvent::Leave { timestamp, .. }
+            | ServerEvent::NickChange { timestamp, .. }
+            | ServerEvent::Direct { timestamp, .. } => Some(*timestamp),
+            ServerEvent::Welcome { .. }
+            | ServerEvent::Roster { .. }
+            | ServerEvent::PresenceChange { .. }
+            | ServerEvent::History { .. }
+            | ServerEvent::Typing { .. }
+            | ServerEvent::NickRejected { .. }
+            | ServerEvent::Error { .. } => None,
+        }
+    }
+}
+
+// A frame as received by the client. Servers that predate the structured
+// protocol (main.py, allinone.py) send pre-formatted text, which is kept
+// as-is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incoming {
+    Event(ServerEvent),
+    Legacy(String),
+}
+
+impl Incoming {
+    // Frames of another protocol version are shown as they are, like
+    // legacy ones, rather than misread.
+    pub fn parse(frame: &str) -> Self {
+        match from_frame(frame) {
+            Some(event) => Incoming::Event(event),
+            None => Incoming::Legacy(frame.to_string()),
+        }
+    }
+
+    // The frame this was parsed from, give or take formatting.
+    pub fn to_frame(&self) -> String {
+        match self {
+            Incoming::Event(event) => event.to_frame(),
+            Incoming::Legacy(text) => text.clone(),
+        }
+    }
+}
+
+// A frame as the Python servers format it: "[12:01:02] alice: hi", with any
+// further lines of the message indented to line up under the text. Their
+// notices come from the nick "System".
+#[derive(Debug, PartialEq)]
+pub struct LegacyLine<'a> {
+    pub time: &'a str,
+    pub nick: &'a str,
+    // Without the indent of further lines.
+    pub text: String,
+}
+
+impl<'a> LegacyLine<'a> {
+    // Returns `None` for frames of any other shape, which are shown as they
+    // are.
+    pub fn parse(frame: &'a str) -> Option<Self> {
+        let (time, rest) = frame.strip_prefix('[')?.split_once("] ")?;
+        if time.len() != 8 || !time.chars().all(|c| c.is_ascii_digit() || c == ':') {
+            return None;
+        }
+        let (nick, text) = rest.split_once(": ")?;
+        if nick.is_empty() {
+            return None;
+        }
+
+        let indent = " ".repeat(time.len() + 3 + nick.chars().count() + 2);
+        let text = text
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => line,
+                _ => line.strip_prefix(indent.as_str()).unwrap_or(line),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(Self { time, nick, text })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip_through_frames() {
+        let event = ServerEvent::NickChange {
+            timestamp: Utc::now(),
+            old_nick: "127.0.0.1".to_string(),
+            new_nick: "alice".to_string(),
+        };
+        let frame = event.to_frame();
+
+        assert!(frame.contains(r#""v":1"#));
+        assert!(frame.contains(r#""type":"nick_change""#));
+        assert_eq!(Incoming::parse(&frame), Incoming::Event(event));
+    }
+
+    #[test]
+    fn legacy_client_frames_are_not_client_messages() {
+        assert_eq!(
+            ClientMessage::parse(r#"{"nick":"alice","message":"hi"}"#),
+            None
+        );
+
+        let join = ClientMessage::Join {
+            room: "rust".to_string(),
+        };
+        assert_eq!(ClientMessage::parse(&join.to_frame()), Some(join));
+    }
+
+    #[test]
+    fn other_versions_are_not_understood() {
+        let frame = ClientMessage::Join {
+            room: "rust".to_string(),
+        }
+        .to_frame()
+        .replace(r#""v":1"#, r#""v":2"#);
+        assert_eq!(ClientMessage::parse(&frame), None);
+        assert_eq!(frame_version(&frame), Some(2));
+        assert_eq!(Incoming::parse(&frame), Incoming::Legacy(frame.clone()));
+        assert_eq!(frame_version(r#"{"nick":"alice","message":"hi"}"#), None);
+    }
+
+    #[test]
+    fn plain_text_frames_are_legacy() {
+        let frame = "[12:01:02] alice: hi";
+        assert_eq!(Incoming::parse(frame), Incoming::Legacy(frame.to_string()));
+        assert_eq!(
+            Incoming::parse(r#"{"nick":"alice"}"#),
+            Incoming::Legacy(r#"{"nick":"alice"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn legacy_lines_are_split_into_time_nick_and_text() {
+        assert_eq!(
+            LegacyLine::parse("[12:01:02] alice: hi: there\n                  again"),
+            Some(LegacyLine {
+                time: "12:01:02",
+                nick: "alice",
+                text: "hi: there\nagain".to_string(),
+            })
+        );
+        assert_eq!(
+            LegacyLine::parse("[12:01:02] System: bob joined the chat").map(|line| line.nick),
+            Some("System")
+        );
+        assert_eq!(LegacyLine::parse("welcome to the chat"), None);
+        assert_eq!(LegacyLine::parse("[noon] alice: hi"), None);
+    }
+}
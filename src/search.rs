@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+// Compiles a search query, a regular expression or plain text matched
+// literally, ignoring case either way.
+pub fn compile(query: &str, regex: bool) -> Result<Regex, String> {
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::Syntax(message) => message.lines().last().unwrap_or("").to_string(),
+            e => e.to_string(),
+        })
+}
+
+// Incremental search over a list of messages, oldest first. Hits are
+// stepped through from the newest one back.
+#[derive(Debug, Default)]
+pub struct Search {
+    query: String,
+    regex: bool,
+    corpus: Vec<String>,
+    pattern: Option<Regex>,
+    error: Option<String>,
+    // Indices of matching messages, and which of them is shown.
+    hits: Vec<usize>,
+    current: Option<usize>,
+}
+
+impl Search {
+    pub fn new(corpus: Vec<String>) -> Self {
+        Self {
+            corpus,
+            ..Self::default()
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.update();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.update();
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    pub fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+        self.update();
+    }
+
+    pub fn set_corpus(&mut self, corpus: Vec<String>) {
+        self.corpus = corpus;
+        self.update();
+    }
+
+    // The compiled query, unless it is empty or invalid.
+    pub fn pattern(&self) -> Option<&Regex> {
+        self.pattern.as_ref()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    // The message index of the hit shown.
+    pub fn current(&self) -> Option<usize> {
+        self.current.map(|hit| self.hits[hit])
+    }
+
+    // Which hit is shown, counting from 1, and how many there are.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.current.map(|hit| (hit + 1, self.hits.len()))
+    }
+
+    // Moves to an older (negative `delta`) or newer hit, stopping at the
+    // ends, and returns its message index.
+    pub fn step(&mut self, delta: isize) -> Option<usize> {
+        let hit = self.current?;
+        let last = self.hits.len() - 1;
+        self.current = Some(hit.saturating_add_signed(delta).min(last));
+        self.current()
+    }
+
+    fn update(&mut self) {
+        self.hits.clear();
+        self.current = None;
+        self.error = None;
+        self.pattern = None;
+        if self.query.is_empty() {
+            return;
+        }
+        match compile(&self.query, self.regex) {
+            Ok(pattern) => self.pattern = Some(pattern),
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        }
+        let pattern = self.pattern.as_ref().expect("compiled above");
+        self.hits = (0..self.corpus.len())
+            .filter(|&i| pattern.is_match(&self.corpus[i]))
+            .collect();
+        self.current = self.hits.len().checked_sub(1);
+    }
+}
+
+// Restyles whatever `pattern` matches in a laid out row with `mark`,
+// splitting runs where a match starts or ends.
+pub fn mark_matches<S: Clone>(
+    row: &[(String, S)],
+    pattern: &Regex,
+    mark: impl Fn(&S) -> S,
+) -> Vec<(String, S)> {
+    let text: String = row.iter().map(|(run, _)| run.as_str()).collect();
+    let matches: Vec<Range<usize>> = pattern
+        .find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| found.range())
+        .collect();
+    if matches.is_empty() {
+        return row.to_vec();
+    }
+
+    let mut marked = Vec::new();
+    let mut run_start = 0;
+    for (run, style) in row {
+        let run_end = run_start + run.len();
+        let mut pos = run_start;
+        for found in matches
+            .iter()
+            .filter(|found| found.start < run_end && found.end > run_start)
+        {
+            let start = found.start.max(run_start);
+            let end = found.end.min(run_end);
+            if pos < start {
+                marked.push((text[pos..start].to_string(), style.clone()));
+            }
+            marked.push((text[start..end].to_string(), mark(style)));
+            pos = end;
+        }
+        if pos < run_end {
+            marked.push((text[pos..run_end].to_string(), style.clone()));
+        }
+        run_start = run_end;
+    }
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<String> {
+        [
+            "alice: deploy at 5",
+            "bob: ok",
+            "alice: Deployed!",
+            "carol: a.b",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn steps_from_the_newest_hit() {
+        let mut search = Search::new(corpus());
+        for c in "deploy".chars() {
+            search.push(c);
+        }
+        assert_eq!(search.position(), Some((2, 2)));
+        assert_eq!(search.current(), Some(2));
+        assert_eq!(search.step(-1), Some(0));
+        assert_eq!(search.step(-1), Some(0));
+        assert_eq!(search.step(1), Some(2));
+        assert_eq!(search.step(1), Some(2));
+
+        search.pop();
+        search.push('y');
+        search.push('e');
+        assert_eq!(search.current(), Some(2));
+    }
+
+    #[test]
+    fn plain_queries_are_literal_and_regexes_can_fail() {
+        let mut search = Search::new(corpus());
+        search.push('.');
+        assert_eq!(search.position(), Some((1, 1)));
+        assert_eq!(search.current(), Some(3));
+
+        search.toggle_regex();
+        assert_eq!(search.position(), Some((4, 4)));
+
+        search.push('(');
+        assert!(search.error().is_some());
+        assert_eq!(search.current(), None);
+    }
+
+    #[test]
+    fn marks_matches_across_runs() {
+        let row = [("[12:00] ".to_string(), 1), ("alice: hi al".to_string(), 2)];
+        let pattern = compile("] AL", false).unwrap();
+        assert_eq!(
+            mark_matches(&row, &pattern, |_| 9),
+            [
+                ("[12:00".to_string(), 1),
+                ("] ".to_string(), 9),
+                ("al".to_string(), 9),
+                ("ice: hi al".to_string(), 2),
+            ]
+        );
+        let pattern = compile("xyz", false).unwrap();
+        assert_eq!(mark_matches(&row, &pattern, |_| 9), row);
+    }
+}
@@ -0,0 +1,680 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use chrono::{DateTime, Local, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{UnboundedSender, unbounded_channel},
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+use crate::{
+    protocol::{
+        ClientMessage, DEFAULT_ROOM, Member, PROTOCOL_VERSION, Presence, ServerEvent,
+        frame_version, valid_room_name,
+    },
+    store::MessageStore,
+};
+
+type PeerId = u64;
+
+struct Peer {
+    nick: String,
+    tx: UnboundedSender<Message>,
+    rooms: BTreeSet<String>,
+    presence: Presence,
+}
+
+// Work for the thread that owns the message store, so a slow disk holds up
+// neither the state lock nor the connections. Jobs run in order, so chat is
+// delivered in the order it was stored, and every other event sent while
+// the server keeps a store passes through here too to stay in line with it.
+enum StoreJob {
+    // Stores a message, then sends it to `recipients`.
+    Chat {
+        room: String,
+        timestamp: DateTime<Utc>,
+        nick: String,
+        text: String,
+        action: bool,
+        recipients: Vec<UnboundedSender<Message>>,
+    },
+    // Sends a page of stored messages, or an error, to `tx`.
+    History {
+        room: String,
+        before: Option<u64>,
+        limit: usize,
+        tx: UnboundedSender<Message>,
+    },
+    // Sends an event that is not stored to `recipients`.
+    Deliver {
+        frame: String,
+        recipients: Vec<UnboundedSender<Message>>,
+    },
+}
+
+fn spawn_store(store: MessageStore) -> mpsc::Sender<StoreJob> {
+    let (jobs_tx, jobs) = mpsc::channel();
+    thread::spawn(move || {
+        let send = |tx: &UnboundedSender<Message>, event: &ServerEvent| {
+            let _ = tx.send(Message::Text(event.to_frame().into()));
+        };
+        for job in jobs {
+            match job {
+                StoreJob::Chat {
+                    room,
+                    timestamp,
+                    nick,
+                    text,
+                    action,
+                    recipients,
+                } => {
+                    // Failing to store a message must not stop it from
+                    // being delivered.
+                    let id = store
+                        .insert(&room, timestamp, &nick, &text, action)
+                        .inspect_err(|e| eprintln!("Storing a message in #{} failed: {}", room, e))
+                        .ok();
+                    let event = ServerEvent::Chat {
+                        id,
+                        timestamp,
+                        room,
+                        nick,
+                        text,
+                        action,
+                    };
+                    for tx in &recipients {
+                        send(tx, &event);
+                    }
+                }
+                StoreJob::History {
+                    room,
+                    before,
+                    limit,
+                    tx,
+                } => match store.page(&room, before, limit) {
+                    Ok((messages, more)) => send(
+                        &tx,
+                        &ServerEvent::History {
+                            room,
+                            messages,
+                            more,
+                        },
+                    ),
+                    Err(e) => {
+                        eprintln!("Reading history of #{} failed: {}", room, e);
+                        let message = format!("Could not read the history of #{}", room);
+                        send(&tx, &ServerEvent::Error { message });
+                    }
+                },
+                StoreJob::Deliver { frame, recipients } => {
+                    for tx in &recipients {
+                        let _ = tx.send(Message::Text(frame.as_str().into()));
+                    }
+                }
+            }
+        }
+    });
+    jobs_tx
+}
+
+#[derive(Default)]
+struct State {
+    peers: HashMap<PeerId, Peer>,
+    next_id: PeerId,
+    // Where chat is stored when the server was started with a database,
+    // and how many of the latest messages a joining client is sent.
+    store: Option<mpsc::Sender<StoreJob>>,
+    replay: usize,
+}
+
+impl State {
+    // Every event goes out through here. With a store, it queues behind the
+    // chat still being stored, so clients see events in the order they
+    // happened.
+    fn deliver<'a>(&self, recipients: impl Iterator<Item = &'a Peer>, event: &ServerEvent) {
+        let recipients: Vec<_> = recipients.map(|peer| peer.tx.clone()).collect();
+        let frame = event.to_frame();
+        match &self.store {
+            Some(store) => {
+                let _ = store.send(StoreJob::Deliver { frame, recipients });
+            }
+            None => {
+                for tx in &recipients {
+                    let _ = tx.send(Message::Text(frame.as_str().into()));
+                }
+            }
+        }
+    }
+
+    fn send(&self, id: PeerId, event: &ServerEvent) {
+        self.deliver(self.peers.get(&id).into_iter(), event);
+    }
+
+    fn broadcast(&self, event: &ServerEvent) {
+        self.deliver(self.peers.values(), event);
+    }
+
+    fn broadcast_room(&self, room: &str, event: &ServerEvent) {
+        let peers = self.peers.values().filter(|peer| peer.rooms.contains(room));
+        self.deliver(peers, event);
+    }
+
+    // Sends `event` to `id` and every peer that shares a room with it, the
+    // ones that can see it.
+    fn broadcast_neighbours(&self, id: PeerId, event: &ServerEvent) {
+        let rooms = &self.peers[&id].rooms;
+        let peers = self
+            .peers
+            .iter()
+            .filter(|(peer_id, peer)| **peer_id == id || !peer.rooms.is_disjoint(rooms))
+            .map(|(_, peer)| peer);
+        self.deliver(peers, event);
+    }
+
+    fn broadcast_room_except(&self, room: &str, id: PeerId, event: &ServerEvent) {
+        let peers = self
+            .peers
+            .iter()
+            .filter(|(peer_id, peer)| **peer_id != id && peer.rooms.contains(room))
+            .map(|(_, peer)| peer);
+        self.deliver(peers, event);
+    }
+
+    fn error(&self, id: PeerId, message: impl Into<String>) {
+        self.send(
+            id,
+            &ServerEvent::Error {
+                message: message.into(),
+            },
+        );
+    }
+
+    // Members are added before the notice goes out, so the joining client
+    // also receives it and knows the join succeeded. The roster follows.
+    fn join(&mut self, id: PeerId, room: &str) {
+        let Some(peer) = self.peers.get_mut(&id) else {
+            return;
+        };
+        if !peer.rooms.insert(room.to_string()) {
+            return;
+        }
+        let nick = peer.nick.clone();
+        self.broadcast_room(
+            room,
+            &ServerEvent::Join {
+                timestamp: Utc::now(),
+                room: room.to_string(),
+                nick,
+            },
+        );
+
+        let mut members: Vec<Member> = self
+            .peers
+            .values()
+            .filter(|peer| peer.rooms.contains(room))
+            .map(|peer| Member {
+                nick: peer.nick.clone(),
+                presence: peer.presence,
+            })
+            .collect();
+        members.sort_by(|a, b| a.nick.cmp(&b.nick));
+        self.send(
+            id,
+            &ServerEvent::Roster {
+                room: room.to_string(),
+                members,
+            },
+        );
+        if self.store.is_some() && self.replay > 0 {
+            self.history(id, room, None, self.replay);
+        }
+    }
+
+    // Sends a page of stored messages. Without a store there is nothing
+    // to send, which is an empty page.
+    fn history(&self, id: PeerId, room: &str, before: Option<u64>, limit: usize) {
+        let (Some(store), Some(peer)) = (&self.store, self.peers.get(&id)) else {
+            self.send(
+                id,
+                &ServerEvent::History {
+                    room: room.to_string(),
+                    messages: Vec::new(),
+                    more: false,
+                },
+            );
+            return;
+        };
+        let _ = store.send(StoreJob::History {
+            room: room.to_string(),
+            before,
+            limit,
+            tx: peer.tx.clone(),
+        });
+    }
+
+    // Sends a chat message to everyone in `room`, storing it first when
+    // the server keeps history.
+    fn chat(&self, room: String, nick: String, text: String, action: bool) {
+        let timestamp = Utc::now();
+        let Some(store) = &self.store else {
+            self.broadcast_room(
+                &room,
+                &ServerEvent::Chat {
+                    id: None,
+                    timestamp,
+                    room: room.clone(),
+                    nick,
+                    text,
+                    action,
+                },
+            );
+            return;
+        };
+        let recipients = self
+            .peers
+            .values()
+            .filter(|peer| peer.rooms.contains(&room))
+            .map(|peer| peer.tx.clone())
+            .collect();
+        let _ = store.send(StoreJob::Chat {
+            room,
+            timestamp,
+            nick,
+            text,
+            action,
+            recipients,
+        });
+    }
+
+    fn set_presence(&mut self, id: PeerId, presence: Presence) {
+        let Some(peer) = self.peers.get_mut(&id) else {
+            return;
+        };
+        if peer.presence == presence {
+            return;
+        }
+        peer.presence = presence;
+        let nick = peer.nick.clone();
+        self.broadcast(&ServerEvent::PresenceChange { nick, presence });
+    }
+
+    fn part(&mut self, id: PeerId, room: &str) {
+        let Some(peer) = self.peers.get_mut(&id) else {
+            return;
+        };
+        if !peer.rooms.remove(room) {
+            return;
+        }
+        let event = ServerEvent::Leave {
+            timestamp: Utc::now(),
+            room: room.to_string(),
+            nick: peer.nick.clone(),
+        };
+        self.send(id, &event);
+        self.broadcast_room(room, &event);
+    }
+
+    // Applies the nick a client asked for, or attached to a legacy message.
+    // Returns false, after telling the client why, when the nick is unusable.
+    fn rename(&mut self, id: PeerId, new_nick: String) -> bool {
+        let old_nick = self.peers[&id].nick.clone();
+        if new_nick == old_nick {
+            return true;
+        }
+
+        let reason = if new_nick.trim().is_empty() {
+            Some("Nickname must not be empty")
+        } else if self.is_taken(&new_nick) {
+            Some("Nickname is already taken")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            self.send(
+                id,
+                &ServerEvent::NickRejected {
+                    nick: new_nick,
+                    reason: reason.to_string(),
+                },
+            );
+            return false;
+        }
+
+        self.peers.get_mut(&id).unwrap().nick = new_nick.clone();
+        self.broadcast_neighbours(
+            id,
+            &ServerEvent::NickChange {
+                timestamp: Utc::now(),
+                old_nick,
+                new_nick,
+            },
+        );
+        true
+    }
+
+    fn is_taken(&self, nick: &str) -> bool {
+        self.peers.values().any(|peer| peer.nick == nick)
+    }
+
+    fn direct(&self, id: PeerId, to: &str, text: String) {
+        let Some(target) = self
+            .peers
+            .iter()
+            .find_map(|(target, peer)| (peer.nick == to).then_some(*target))
+        else {
+            self.error(id, format!("{} is not online", to));
+            return;
+        };
+        let event = ServerEvent::Direct {
+            timestamp: Utc::now(),
+            from: self.peers[&id].nick.clone(),
+            to: to.to_string(),
+            text,
+        };
+        self.send(target, &event);
+        if target != id {
+            self.send(id, &event);
+        }
+    }
+}
+
+// What a client asked for, whichever protocol it spoke.
+enum Request {
+    Chat {
+        nick: Option<String>,
+        room: String,
+        text: String,
+        action: bool,
+    },
+    Join(String),
+    Part(String),
+    Nick(String),
+    Presence(Presence),
+    Typing {
+        room: String,
+        typing: bool,
+    },
+    Direct {
+        to: String,
+        text: String,
+    },
+    History {
+        room: String,
+        before: Option<u64>,
+        limit: usize,
+    },
+    // A structured frame of a protocol version this server does not speak.
+    Unsupported(u32),
+}
+
+fn parse_request(text: String) -> Request {
+    if let Some(message) = ClientMessage::parse(&text) {
+        return match message {
+            // Structured clients rename with `Nick`; the nick on their
+            // messages is only informational, so a refused rename cannot
+            // swallow what they send afterwards.
+            ClientMessage::Chat {
+                room, text, action, ..
+            } => Request::Chat {
+                nick: None,
+                room,
+                text,
+                action,
+            },
+            ClientMessage::Join { room } => Request::Join(room),
+            ClientMessage::Part { room } => Request::Part(room),
+            ClientMessage::Nick { nick } => Request::Nick(nick),
+            ClientMessage::Presence { presence } => Request::Presence(presence),
+            ClientMessage::Typing { room, typing } => Request::Typing { room, typing },
+            ClientMessage::Direct { to, text, .. } => Request::Direct { to, text },
+            ClientMessage::History {
+                room,
+                before,
+                limit,
+            } => Request::History {
+                room,
+                before,
+                limit,
+            },
+        };
+    }
+    if let Some(version) = frame_version(&text) {
+        return Request::Unsupported(version);
+    }
+
+    let (nick, text) = match serde_json::from_str::<Value>(&text) {
+        Ok(data) => match (data["nick"].as_str(), data["message"].as_str()) {
+            (Some(nick), Some(message)) => (Some(nick.to_string()), message.to_string()),
+            _ => (None, text),
+        },
+        Err(_) => (None, text),
+    };
+    Request::Chat {
+        nick,
+        room: DEFAULT_ROOM.to_string(),
+        text,
+        action: false,
+    }
+}
+
+// Chat server with the same behaviour as main.py: clients send either
+// `{"nick": ..., "message": ...}` JSON or raw text, which lands in the default
+// room, or structured `ClientMessage` frames. Unlike main.py it answers with
+// structured `ServerEvent` frames instead of pre-formatted lines.
+pub struct Server {
+    listener: TcpListener,
+    state: Arc<Mutex<State>>,
+}
+
+impl Server {
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            state: Arc::default(),
+        })
+    }
+
+    // Keeps chat in `store` and sends the latest `replay` messages of a
+    // room to everyone joining it.
+    pub fn with_store(self, store: MessageStore, replay: usize) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.store = Some(spawn_store(store));
+            state.replay = replay;
+        }
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn run(self) -> io::Result<()> {
+        loop {
+            let (tcp, addr) = self.listener.accept().await?;
+            let state = Arc::clone(&self.state);
+            tokio::spawn(handle_connection(tcp, addr, state));
+        }
+    }
+}
+
+async fn handle_connection(tcp: TcpStream, addr: SocketAddr, state: Arc<Mutex<State>>) {
+    let socket = match accept_async(tcp).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Handshake with {} failed: {}", addr, e);
+            return;
+        }
+    };
+    println!("New connection from {}", addr.ip());
+
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Until a client names itself, it is known by its IP address, suffixed
+    // when several clients share one.
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let mut nick = addr.ip().to_string();
+        let mut suffix = 1;
+        while state.is_taken(&nick) {
+            suffix += 1;
+            nick = format!("{}-{}", addr.ip(), suffix);
+        }
+        state.peers.insert(
+            id,
+            Peer {
+                nick: nick.clone(),
+                tx,
+                rooms: BTreeSet::new(),
+                presence: Presence::Active,
+            },
+        );
+        state.send(
+            id,
+            &ServerEvent::Welcome {
+                version: PROTOCOL_VERSION,
+                nick,
+            },
+        );
+        state.join(id, DEFAULT_ROOM);
+        id
+    };
+
+    while let Some(message) = stream.next().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text.to_string(),
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        println!(
+            "[{}] Received from {}: {}",
+            Local::now().format("%H:%M:%S"),
+            addr.ip(),
+            text
+        );
+
+        let mut state = state.lock().unwrap();
+        let (new_nick, room, body, action) = match parse_request(text) {
+            Request::Chat {
+                nick,
+                room,
+                text,
+                action,
+            } => (nick, room, text, action),
+            Request::Join(room) if valid_room_name(&room) => {
+                state.join(id, &room);
+                continue;
+            }
+            Request::Part(room) => {
+                state.part(id, &room);
+                continue;
+            }
+            Request::Join(room) => {
+                state.error(id, format!("Invalid room name '{}'", room));
+                continue;
+            }
+            Request::Nick(nick) => {
+                state.rename(id, nick);
+                continue;
+            }
+            Request::Presence(presence) => {
+                state.set_presence(id, presence);
+                continue;
+            }
+            Request::Typing { room, typing } => {
+                if let Some(peer) = state.peers.get(&id)
+                    && peer.rooms.contains(&room)
+                {
+                    let event = ServerEvent::Typing {
+                        room: room.clone(),
+                        nick: peer.nick.clone(),
+                        typing,
+                    };
+                    state.broadcast_room_except(&room, id, &event);
+                }
+                continue;
+            }
+            Request::Direct { to, text } => {
+                if !text.trim().is_empty() {
+                    state.direct(id, &to, text);
+                }
+                continue;
+            }
+            Request::History {
+                room,
+                before,
+                limit,
+            } => {
+                if state.peers[&id].rooms.contains(&room) {
+                    state.history(id, &room, before, limit);
+                } else {
+                    state.error(id, format!("You are not in #{}", room));
+                }
+                continue;
+            }
+            Request::Unsupported(version) => {
+                state.error(
+                    id,
+                    format!(
+                        "Unsupported protocol version {} (this server speaks {})",
+                        version, PROTOCOL_VERSION
+                    ),
+                );
+                continue;
+            }
+        };
+
+        // A refused nick leaves the current one in place; the message is
+        // still delivered under it.
+        if let Some(new_nick) = new_nick {
+            state.rename(id, new_nick);
+        }
+        if body.trim().is_empty() {
+            continue;
+        }
+        if !valid_room_name(&room) {
+            state.error(id, format!("Invalid room name '{}'", room));
+            continue;
+        }
+        // Talking in a room implies being in it.
+        state.join(id, &room);
+        let nick = state.peers[&id].nick.clone();
+        state.chat(room, nick, body, action);
+    }
+
+    {
+        let mut state = state.lock().unwrap();
+        if let Some(peer) = state.peers.remove(&id) {
+            for room in &peer.rooms {
+                state.broadcast_room(
+                    room,
+                    &ServerEvent::Leave {
+                        timestamp: Utc::now(),
+                        room: room.clone(),
+                        nick: peer.nick.clone(),
+                    },
+                );
+            }
+        }
+    }
+    writer.abort();
+    println!("Connection closed: {}", addr.ip());
+}
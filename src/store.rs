@@ -0,0 +1,139 @@
+use std::{io, path::Path};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+
+use crate::protocol::StoredMessage;
+
+// How many stored messages a client may ask for at once.
+pub const MAX_PAGE: usize = 200;
+
+// Chat messages of every room, kept by the server in an SQLite database so
+// that clients joining later can catch up. Direct messages are not stored.
+pub struct MessageStore {
+    db: Connection,
+}
+
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl MessageStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::init(Connection::open(path).map_err(db_error)?)
+    }
+
+    // A store that is forgotten when dropped, for tests.
+    pub fn in_memory() -> io::Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(db_error)?)
+    }
+
+    fn init(db: Connection) -> io::Result<Self> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 room TEXT NOT NULL,
+                 timestamp TEXT NOT NULL,
+                 nick TEXT NOT NULL,
+                 text TEXT NOT NULL,
+                 action INTEGER NOT NULL DEFAULT 0
+             );
+             CREATE INDEX IF NOT EXISTS messages_by_room ON messages (room, id);",
+        )
+        .map_err(db_error)?;
+        Ok(Self { db })
+    }
+
+    // Stores a message and returns its id.
+    pub fn insert(
+        &self,
+        room: &str,
+        timestamp: DateTime<Utc>,
+        nick: &str,
+        text: &str,
+        action: bool,
+    ) -> io::Result<u64> {
+        self.db
+            .execute(
+                "INSERT INTO messages (room, timestamp, nick, text, action)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![room, timestamp.to_rfc3339(), nick, text, action],
+            )
+            .map_err(db_error)?;
+        Ok(self.db.last_insert_rowid() as u64)
+    }
+
+    // Up to `limit` messages of `room` from before message `before`, or the
+    // newest ones, oldest first, and whether there are older ones still.
+    pub fn page(
+        &self,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> io::Result<(Vec<StoredMessage>, bool)> {
+        let limit = limit.min(MAX_PAGE);
+        let mut query = self
+            .db
+            .prepare(
+                "SELECT id, timestamp, nick, text, action FROM messages
+                 WHERE room = ?1 AND id < ?2 ORDER BY id DESC LIMIT ?3",
+            )
+            .map_err(db_error)?;
+        let before = before.map_or(i64::MAX, |id| id as i64);
+        let mut messages = query
+            .query_map(params![room, before, limit as i64 + 1], |row| {
+                let timestamp: String = row.get(1)?;
+                Ok(StoredMessage {
+                    id: row.get::<_, i64>(0)? as u64,
+                    timestamp: DateTime::parse_from_rfc3339(&timestamp)
+                        .map(|timestamp| timestamp.with_timezone(&Utc))
+                        .unwrap_or_default(),
+                    nick: row.get(2)?,
+                    text: row.get(3)?,
+                    action: row.get(4)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        let more = messages.len() > limit;
+        messages.truncate(limit);
+        messages.reverse();
+        Ok((messages, more))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_go_back_in_time_per_room() {
+        let store = MessageStore::in_memory().unwrap();
+        for i in 0..5 {
+            store
+                .insert("general", Utc::now(), "alice", &i.to_string(), false)
+                .unwrap();
+            store
+                .insert("rust", Utc::now(), "bob", "elsewhere", true)
+                .unwrap();
+        }
+        let texts = |messages: &[StoredMessage]| {
+            messages
+                .iter()
+                .map(|message| message.text.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let (latest, more) = store.page("general", None, 2).unwrap();
+        assert_eq!(texts(&latest), ["3", "4"]);
+        assert!(more);
+        let (older, more) = store.page("general", Some(latest[0].id), 10).unwrap();
+        assert_eq!(texts(&older), ["0", "1", "2"]);
+        assert!(!more);
+
+        let (rust, _) = store.page("rust", None, 1).unwrap();
+        assert!(rust[0].action);
+        assert!(store.page("random", None, 10).unwrap().0.is_empty());
+    }
+}
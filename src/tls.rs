@@ -0,0 +1,273 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub ca_file: Option<PathBuf>,
+    pub insecure: bool,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+fn pem_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), e),
+    )
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(path, e))?;
+    if certs.is_empty() {
+        return Err(pem_error(path, "no certificates found"));
+    }
+    Ok(certs)
+}
+
+// Builds the rustls configuration used for wss:// connections: the bundled
+// webpki roots plus an optional extra CA file, optional client certificate
+// for mutual TLS, or no verification at all when `insecure` is set.
+pub fn client_config(options: &TlsOptions) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let builder = if options.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(path) = &options.ca_file {
+            for cert in load_certs(path)? {
+                roots.add(cert).map_err(|e| pem_error(path, e))?;
+            }
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    let config = match (&options.client_cert, &options.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = load_certs(cert_path)?;
+            let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(io::Error::other)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--client-cert and --client-key must be given together",
+            ));
+        }
+    };
+
+    Ok(Arc::new(config))
+}
+
+// Accepts any server certificate. Only meant for talking to local test
+// servers with self-signed certificates.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use rustls::{ServerConfig, server::WebPkiClientVerifier};
+    use std::fs;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+    use tokio_tungstenite::{Connector, accept_async, connect_async_tls_with_config};
+
+    fn temp_pem(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tls-test-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn server_identity() -> (CertifiedKey, ServerConfig) {
+        let identity = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![identity.cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(identity.key_pair.serialize_der().into()),
+                )
+                .unwrap();
+        (identity, config)
+    }
+
+    // Accepts a single wss:// client and reports whether the handshake
+    // completed.
+    async fn serve_once(config: ServerConfig) -> (String, tokio::task::JoinHandle<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("wss://localhost:{}", listener.local_addr().unwrap().port());
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let handle = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            match acceptor.accept(tcp).await {
+                Ok(stream) => accept_async(stream).await.is_ok(),
+                Err(_) => false,
+            }
+        });
+        (url, handle)
+    }
+
+    async fn connect(url: &str, options: &TlsOptions) -> bool {
+        let config = client_config(options).unwrap();
+        connect_async_tls_with_config(url, None, false, Some(Connector::Rustls(config)))
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn trusts_extra_ca_file() {
+        let (identity, config) = server_identity();
+        let (url, server) = serve_once(config).await;
+        let ca_file = temp_pem("ca.pem", &identity.cert.pem());
+
+        let options = TlsOptions {
+            ca_file: Some(ca_file.clone()),
+            ..TlsOptions::default()
+        };
+        assert!(connect(&url, &options).await);
+        assert!(server.await.unwrap());
+
+        fs::remove_file(ca_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_self_signed_server_by_default() {
+        let (_identity, config) = server_identity();
+        let (url, _server) = serve_once(config).await;
+
+        assert!(!connect(&url, &TlsOptions::default()).await);
+    }
+
+    #[tokio::test]
+    async fn insecure_skips_verification() {
+        let (_identity, config) = server_identity();
+        let (url, server) = serve_once(config).await;
+
+        let options = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        assert!(connect(&url, &options).await);
+        assert!(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn presents_client_certificate() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let client = CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let provider = Arc::new(crypto::ring::default_provider());
+        let mut client_roots = RootCertStore::empty();
+        client_roots.add(ca.der().clone()).unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(client_roots), provider.clone())
+                .build()
+                .unwrap();
+
+        let identity = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![identity.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(identity.key_pair.serialize_der().into()),
+            )
+            .unwrap();
+        let (url, server) = serve_once(config).await;
+
+        let ca_file = temp_pem("server-ca.pem", &identity.cert.pem());
+        let cert_file = temp_pem("client.pem", &client.pem());
+        let key_file = temp_pem("client.key", &client_key.serialize_pem());
+        let options = TlsOptions {
+            ca_file: Some(ca_file.clone()),
+            insecure: false,
+            client_cert: Some(cert_file.clone()),
+            client_key: Some(key_file.clone()),
+        };
+        assert!(connect(&url, &options).await);
+        assert!(server.await.unwrap());
+
+        for path in [ca_file, cert_file, key_file] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Incoming, ServerEvent};
+
+// What a logged line records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    Chat,
+    Action,
+    Direct,
+    Join,
+    Leave,
+    Nick,
+    System,
+    Error,
+}
+
+// One line of a transcript, as written to the logs and read back for
+// exporting. `text` is the room for joins and leaves and the new nick for
+// nick changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub kind: EntryKind,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub nick: String,
+    pub text: String,
+}
+
+impl LogEntry {
+    // Events without a timestamp of their own are stamped with `now`;
+    // frames that only update state are not logged.
+    pub fn from_incoming(incoming: &Incoming, now: DateTime<Utc>) -> Option<Self> {
+        let entry = |timestamp: &DateTime<Utc>, kind, nick: &str, text: &str| {
+            Some(Self {
+                timestamp: *timestamp,
+                kind,
+                nick: nick.to_string(),
+                text: text.to_string(),
+            })
+        };
+        let event = match incoming {
+            Incoming::Legacy(text) => return entry(&now, EntryKind::System, "", text),
+            Incoming::Event(event) => event,
+        };
+        match event {
+            ServerEvent::Chat {
+                timestamp,
+                nick,
+                text,
+                action,
+                ..
+            } => {
+                let kind = if *action {
+                    EntryKind::Action
+                } else {
+                    EntryKind::Chat
+                };
+                entry(timestamp, kind, nick, text)
+            }
+            ServerEvent::Direct {
+                timestamp,
+                from,
+                text,
+                ..
+            } => entry(timestamp, EntryKind::Direct, from, text),
+            ServerEvent::Join {
+                timestamp,
+                room,
+                nick,
+            } => entry(timestamp, EntryKind::Join, nick, &format!("#{}", room)),
+            ServerEvent::Leave {
+                timestamp,
+                room,
+                nick,
+            } => entry(timestamp, EntryKind::Leave, nick, &format!("#{}", room)),
+            ServerEvent::NickChange {
+                timestamp,
+                old_nick,
+                new_nick,
+            } => entry(timestamp, EntryKind::Nick, old_nick, new_nick),
+            ServerEvent::System { timestamp, text } => {
+                entry(timestamp, EntryKind::System, "", text)
+            }
+            ServerEvent::NickRejected { nick, reason } => entry(
+                &now,
+                EntryKind::Error,
+                "",
+                &format!("cannot use nickname '{}': {}", nick, reason),
+            ),
+            ServerEvent::Error { message } => entry(&now, EntryKind::Error, "", message),
+            ServerEvent::Welcome { .. }
+            | ServerEvent::Roster { .. }
+            | ServerEvent::PresenceChange { .. }
+            | ServerEvent::History { .. }
+            | ServerEvent::Typing { .. } => None,
+        }
+    }
+
+    // The event the entry was logged from, near enough to show it again;
+    // direct messages come back as chat in `room`, the `@nick` of the
+    // conversation.
+    pub fn to_incoming(&self, room: &str) -> Incoming {
+        let timestamp = self.timestamp;
+        let event = match self.kind {
+            EntryKind::Chat | EntryKind::Action | EntryKind::Direct => ServerEvent::Chat {
+                id: None,
+                timestamp,
+                room: room.to_string(),
+                nick: self.nick.clone(),
+                text: self.text.clone(),
+                action: self.kind == EntryKind::Action,
+            },
+            EntryKind::Join => ServerEvent::Join {
+                timestamp,
+                room: self.text.trim_start_matches('#').to_string(),
+                nick: self.nick.clone(),
+            },
+            EntryKind::Leave => ServerEvent::Leave {
+                timestamp,
+                room: self.text.trim_start_matches('#').to_string(),
+                nick: self.nick.clone(),
+            },
+            EntryKind::Nick => ServerEvent::NickChange {
+                timestamp,
+                old_nick: self.nick.clone(),
+                new_nick: self.text.clone(),
+            },
+            EntryKind::System => ServerEvent::System {
+                timestamp,
+                text: self.text.clone(),
+            },
+            EntryKind::Error => ServerEvent::Error {
+                message: self.text.clone(),
+            },
+        };
+        Incoming::Event(event)
+    }
+
+    // The line for entries that are not someone talking, e.g. "bob has
+    // joined #general".
+    pub fn describe(&self) -> String {
+        match self.kind {
+            EntryKind::Join => format!("{} has joined {}", self.nick, self.text),
+            EntryKind::Leave => format!("{} has left {}", self.nick, self.text),
+            EntryKind::Nick => format!("{} is now known as {}", self.nick, self.text),
+            EntryKind::Action => format!("* {} {}", self.nick, self.text),
+            EntryKind::Error => format!("Error: {}", self.text),
+            EntryKind::Chat | EntryKind::Direct | EntryKind::System => self.text.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    // irssi-style lines: "12:00:00 <alice> hello".
+    #[default]
+    Plain,
+    // One `LogEntry` as JSON per line.
+    Jsonl,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" | "text" => Ok(LogFormat::Plain),
+            "jsonl" | "json" => Ok(LogFormat::Jsonl),
+            _ => Err(format!("Unknown log format '{}' (plain or jsonl)", s)),
+        }
+    }
+}
+
+impl LogFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Plain => "log",
+            LogFormat::Jsonl => "jsonl",
+        }
+    }
+
+    fn format(self, entry: &LogEntry) -> String {
+        match self {
+            LogFormat::Plain => {
+                let time = entry.timestamp.with_timezone(&Local).format("%H:%M:%S");
+                let prefix = match entry.kind {
+                    EntryKind::Chat | EntryKind::Direct => format!("{} <{}> ", time, entry.nick),
+                    EntryKind::Action => format!("{}  * {} ", time, entry.nick),
+                    _ => format!("{} -!- ", time),
+                };
+                let text = match entry.kind {
+                    EntryKind::Chat | EntryKind::Direct | EntryKind::Action => entry.text.clone(),
+                    _ => entry.describe(),
+                };
+                let mut lines = text.split('\n');
+                let mut formatted = format!("{}{}\n", prefix, lines.next().unwrap_or(""));
+                for line in lines {
+                    formatted.push_str(&format!("{}{}\n", CONTINUATION, line));
+                }
+                formatted
+            }
+            LogFormat::Jsonl => {
+                let mut line = serde_json::to_string(entry).expect("log entries always serialize");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+// Continuation lines of a multi-line message in the plain format are
+// indented to line up under the nick, which no line of its own is.
+const CONTINUATION: &str = "         ";
+
+// Parses a size such as "512K", "10M" or "1048576" into bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("invalid size '{}'", size)),
+    };
+    let n = digits
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size '{}'", size))?;
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", size))
+}
+
+// Turns a room name into a single path component. Room names may contain
+// anything but whitespace, and come from servers that may not check them, so
+// they must not name a directory, let alone one outside the intended one.
+pub fn safe_file_name(room: &str) -> String {
+    let name = room.replace(['/', '\\'], "_");
+    if name.chars().all(|c| c == '.') {
+        name.replace('.', "_") + "_"
+    } else {
+        name
+    }
+}
+
+// Appends chat to one file per room and local day,
+// `<dir>/<room>/<YYYY-MM-DD>.log` (or `.jsonl`). A file that would grow past
+// `max_bytes` is rotated to `.1`, the previous `.1` to `.2` and so on,
+// keeping at most `keep` old files per day.
+#[derive(Clone, Debug)]
+pub struct TranscriptLog {
+    dir: PathBuf,
+    format: LogFormat,
+    max_bytes: Option<u64>,
+    keep: usize,
+}
+
+impl TranscriptLog {
+    pub fn new(dir: impl Into<PathBuf>, format: LogFormat) -> Self {
+        Self {
+            dir: dir.into(),
+            format,
+            max_bytes: None,
+            keep: 0,
+        }
+    }
+
+    pub fn with_rotation(mut self, max_bytes: u64, keep: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self.keep = keep;
+        self
+    }
+
+    fn room_dir(&self, room: &str) -> PathBuf {
+        self.dir.join(safe_file_name(room))
+    }
+
+    pub fn append(&self, room: &str, entry: &LogEntry) -> io::Result<()> {
+        let date = entry.timestamp.with_timezone(&Local).date_naive();
+        let dir = self.room_dir(room);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.{}", date, self.format.extension()));
+
+        let mut text = self.format.format(entry);
+        let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        let size = match self.max_bytes {
+            Some(max) if size > 0 && size + text.len() as u64 > max => {
+                self.rotate(&path)?;
+                0
+            }
+            _ => size,
+        };
+        if size == 0 && self.format == LogFormat::Plain {
+            let opened = Local::now().format("%a %b %e %H:%M:%S %Y");
+            text = format!("--- Log opened {}\n{}", opened, text);
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(text.as_bytes())
+    }
+
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        if self.keep == 0 {
+            return fs::remove_file(path);
+        }
+        let _ = fs::remove_file(rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(path, rotated(1))
+    }
+
+    // Everything logged for `room` between `from` and `to`, oldest first,
+    // whichever format each file was written in.
+    pub fn read(
+        &self,
+        room: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> io::Result<Vec<LogEntry>> {
+        let dir = self.room_dir(room);
+        let listing = fs::read_dir(&dir)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+        let mut files = Vec::new();
+        for dir_entry in listing {
+            let path = dir_entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // "2026-10-17.log", or "2026-10-17.log.2" once rotated.
+            let mut parts = name.splitn(3, '.');
+            let (Some(date), Some(extension)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+                continue;
+            };
+            let rotation = parts
+                .next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or(0);
+            let jsonl = extension == LogFormat::Jsonl.extension();
+            files.push((date, std::cmp::Reverse(rotation), jsonl, path));
+        }
+        files.sort();
+
+        let mut entries = Vec::new();
+        for (date, _, jsonl, path) in files {
+            let contents = fs::read_to_string(&path)?;
+            let parsed = if jsonl {
+                contents
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            } else {
+                parse_plain(&contents, date)
+            };
+            entries.extend(parsed.into_iter().filter(|entry: &LogEntry| {
+                from.is_none_or(|from| entry.timestamp >= from)
+                    && to.is_none_or(|to| entry.timestamp <= to)
+            }));
+        }
+        // Rotated files of one day can overlap when a clock moved back.
+        entries.sort_by_key(|entry| entry.timestamp);
+        Ok(entries)
+    }
+}
+
+// Reads back a file written in the plain format, joining continuation
+// lines onto the message they belong to.
+fn parse_plain(contents: &str, date: NaiveDate) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = Vec::new();
+    for line in contents.lines() {
+        if let Some(continued) = line.strip_prefix(CONTINUATION) {
+            if let Some(entry) = entries.last_mut() {
+                entry.text.push('\n');
+                entry.text.push_str(continued);
+            }
+        } else if let Some(entry) = parse_plain_line(line, date) {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+// Reads back a line written in the plain format. Notices such as joins come
+// back as system entries with their text as logged.
+fn parse_plain_line(line: &str, date: NaiveDate) -> Option<LogEntry> {
+    let (time, rest) = line.split_once(' ')?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+    let timestamp = Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()?
+        .with_timezone(&Utc);
+    let (kind, nick, text) = if let Some(rest) = rest.strip_prefix('<') {
+        let (nick, text) = rest.split_once("> ")?;
+        (EntryKind::Chat, nick, text)
+    } else if let Some(rest) = rest.strip_prefix(" * ") {
+        let (nick, text) = rest.split_once(' ').unwrap_or((rest, ""));
+        (EntryKind::Action, nick, text)
+    } else {
+        (EntryKind::System, "", rest.strip_prefix("-!- ")?)
+    };
+    Some(LogEntry {
+        timestamp,
+        kind,
+        nick: nick.to_string(),
+        text: text.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: EntryKind, nick: &str, text: &str) -> LogEntry {
+        LogEntry {
+            timestamp: Local
+                .with_ymd_and_hms(2026, 10, 17, 12, 30, 0)
+                .unwrap()
+                .with_timezone(&Utc),
+            kind,
+            nick: nick.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn plain_logs_look_like_irssi_and_read_back() {
+        let dir = std::env::temp_dir().join(format!("transcript-test-{}", std::process::id()));
+        let log = TranscriptLog::new(&dir, LogFormat::Plain);
+        log.append("general", &entry(EntryKind::Chat, "alice", "hi\nthere"))
+            .unwrap();
+        log.append("general", &entry(EntryKind::Action, "bob", "waves"))
+            .unwrap();
+        log.append("general", &entry(EntryKind::Join, "carol", "#general"))
+            .unwrap();
+
+        let contents = fs::read_to_string(dir.join("general").join("2026-10-17.log")).unwrap();
+        let lines: Vec<&str> = contents.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            [
+                "12:30:00 <alice> hi",
+                "         there",
+                "12:30:00  * bob waves",
+                "12:30:00 -!- carol has joined #general",
+            ]
+        );
+
+        let entries = log.read("general", None, None).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], entry(EntryKind::Chat, "alice", "hi\nthere"));
+        assert_eq!(entries[1], entry(EntryKind::Action, "bob", "waves"));
+        assert_eq!(entries[2].text, "carol has joined #general");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn room_names_stay_inside_the_log_dir() {
+        let log = TranscriptLog::new(Path::new("logs"), LogFormat::Plain);
+        assert_eq!(log.room_dir(".."), Path::new("logs").join("___"));
+        assert_eq!(log.room_dir("."), Path::new("logs").join("__"));
+        assert_eq!(log.room_dir("a/../b"), Path::new("logs").join("a_.._b"));
+        assert_eq!(log.room_dir(".rust"), Path::new("logs").join(".rust"));
+    }
+
+    #[test]
+    fn full_files_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("rotation-test-{}", std::process::id()));
+        let log = TranscriptLog::new(&dir, LogFormat::Jsonl).with_rotation(200, 2);
+        for i in 0..10 {
+            log.append("rust", &entry(EntryKind::Chat, "bob", &i.to_string()))
+                .unwrap();
+        }
+
+        let day = dir.join("rust").join("2026-10-17.jsonl");
+        for path in [
+            day.clone(),
+            day.with_extension("jsonl.1"),
+            day.with_extension("jsonl.2"),
+        ] {
+            let size = fs::metadata(&path).unwrap().len();
+            assert!(
+                size > 0 && size <= 200,
+                "{} is {} bytes",
+                path.display(),
+                size
+            );
+        }
+        assert!(!day.with_extension("jsonl.3").exists());
+        let texts: Vec<String> = log
+            .read("rust", None, None)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.text)
+            .collect();
+        assert_eq!(texts.last().unwrap(), "9");
+        assert!(texts.len() < 10);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn entries_turn_back_into_events() {
+        let now = Utc::now();
+        for entry in [
+            entry(EntryKind::Chat, "alice", "hi"),
+            entry(EntryKind::Action, "bob", "waves"),
+            entry(EntryKind::Join, "carol", "#general"),
+            entry(EntryKind::Nick, "carol", "caz"),
+        ] {
+            let incoming = entry.to_incoming("general");
+            assert_eq!(LogEntry::from_incoming(&incoming, now), Some(entry));
+        }
+    }
+
+    #[test]
+    fn sizes_take_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("10MB"), Ok(10 << 20));
+        assert!(parse_size("ten").is_err());
+    }
+
+    #[test]
+    fn oversized_sizes_are_refused() {
+        assert_eq!(
+            parse_size("17179869184G"),
+            Err("size '17179869184G' is too large".to_string())
+        );
+        assert_eq!(parse_size("16777215G"), Ok(16777215 << 30));
+    }
+}
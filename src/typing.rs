@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// While the user keeps typing, "started" is repeated this often so that
+// receivers can expire indicators whose "stopped" never arrived.
+pub const TYPING_REFRESH: Duration = Duration::from_secs(3);
+// No edits for this long counts as having stopped typing.
+pub const TYPING_IDLE: Duration = Duration::from_secs(5);
+// Receivers drop an indicator not refreshed for this long.
+pub const TYPING_EXPIRY: Duration = Duration::from_secs(6);
+
+// Decides when the local user's edits should be announced, so that a burst
+// of key presses sends one "started" instead of one per key.
+#[derive(Debug, Default)]
+pub struct TypingNotifier {
+    typing: bool,
+    last_sent: Option<Instant>,
+    last_edit: Option<Instant>,
+}
+
+impl TypingNotifier {
+    // Returns true when "started" should be sent.
+    pub fn edited(&mut self, now: Instant) -> bool {
+        self.last_edit = Some(now);
+        let due = self
+            .last_sent
+            .is_none_or(|sent| now.duration_since(sent) >= TYPING_REFRESH);
+        if self.typing && !due {
+            return false;
+        }
+        self.typing = true;
+        self.last_sent = Some(now);
+        true
+    }
+
+    // Returns true when "stopped" should be sent because the user went quiet.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let quiet = self
+            .last_edit
+            .is_some_and(|edit| now.duration_since(edit) >= TYPING_IDLE);
+        quiet && self.stop()
+    }
+
+    // Returns true when "stopped" should be sent, e.g. after the message was
+    // sent or the input cleared.
+    pub fn stop(&mut self) -> bool {
+        let was_typing = self.typing;
+        self.typing = false;
+        self.last_sent = None;
+        was_typing
+    }
+}
+
+// Who is typing where, as announced by others.
+#[derive(Debug, Default)]
+pub struct TypingTracker {
+    heard: HashMap<(String, String), Instant>,
+}
+
+impl TypingTracker {
+    pub fn update(&mut self, room: &str, nick: &str, typing: bool, now: Instant) {
+        let key = (room.to_string(), nick.to_string());
+        if typing {
+            self.heard.insert(key, now);
+        } else {
+            self.heard.remove(&key);
+        }
+    }
+
+    // Nicks typing in `room`, sorted, forgetting anyone gone quiet.
+    pub fn typists(&mut self, room: &str, now: Instant) -> Vec<String> {
+        self.heard
+            .retain(|_, heard| now.duration_since(*heard) < TYPING_EXPIRY);
+        let mut nicks: Vec<String> = self
+            .heard
+            .keys()
+            .filter(|(typing_room, _)| typing_room == room)
+            .map(|(_, nick)| nick.clone())
+            .collect();
+        nicks.sort();
+        nicks
+    }
+}
+
+// "alice is typing…", "alice and bob are typing…", and so on.
+pub fn describe(nicks: &[String]) -> Option<String> {
+    match nicks {
+        [] => None,
+        [one] => Some(format!("{} is typing…", one)),
+        [first, second] => Some(format!("{} and {} are typing…", first, second)),
+        [first, second, rest @ ..] => Some(format!(
+            "{}, {} and {} more are typing…",
+            first,
+            second,
+            rest.len()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifier_throttles_and_stops_after_silence() {
+        let start = Instant::now();
+        let mut notifier = TypingNotifier::default();
+
+        assert!(notifier.edited(start));
+        assert!(!notifier.edited(start + Duration::from_secs(1)));
+        assert!(notifier.edited(start + TYPING_REFRESH));
+        assert!(!notifier.tick(start + TYPING_REFRESH + Duration::from_secs(1)));
+        assert!(notifier.tick(start + TYPING_REFRESH + TYPING_IDLE));
+        assert!(!notifier.stop());
+    }
+
+    #[test]
+    fn tracker_expires_silent_typists() {
+        let start = Instant::now();
+        let mut tracker = TypingTracker::default();
+        tracker.update("general", "bob", true, start);
+        tracker.update("general", "alice", true, start + Duration::from_secs(2));
+        tracker.update("rust", "carol", true, start);
+
+        assert_eq!(tracker.typists("general", start), ["alice", "bob"]);
+        assert_eq!(tracker.typists("general", start + TYPING_EXPIRY), ["alice"]);
+        tracker.update("general", "alice", false, start + TYPING_EXPIRY);
+        assert!(tracker.typists("general", start + TYPING_EXPIRY).is_empty());
+    }
+
+    #[test]
+    fn describes_typists() {
+        let nicks = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(describe(&[]), None);
+        assert_eq!(
+            describe(&nicks(&["alice", "bob"])).unwrap(),
+            "alice and bob are typing…"
+        );
+        assert_eq!(
+            describe(&nicks(&["a", "b", "c", "d"])).unwrap(),
+            "a, b and 2 more are typing…"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::mpsc,
+    time::Duration,
+};
+
+use termchat::{
+    client::ChatClient,
+    connection::{ConnectOptions, ConnectionState},
+    outbox::Outbox,
+    protocol::{Incoming, ServerEvent},
+    server::Server,
+};
+
+async fn start_server() -> String {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    tokio::spawn(server.run());
+    url
+}
+
+// Runs a server on a runtime of its own, so shutting that down drops every
+// connection the way a crash would, and the server can start again on the
+// same address. Stop it with `shutdown_background`, since dropping a runtime
+// inside another one panics.
+fn serve(addr: SocketAddr) -> tokio::runtime::Runtime {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.spawn(async move {
+        // The previous server may take a moment to let go of the port.
+        let server = loop {
+            match Server::bind(addr).await {
+                Ok(server) => break server,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        server.run().await
+    });
+    runtime
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn wait_until(mut condition: impl FnMut() -> bool) {
+    for _ in 0..500 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition never became true");
+}
+
+fn renamed_to(events: &mpsc::Receiver<Incoming>, nick: &str) -> bool {
+    events.try_iter().any(|incoming| {
+        matches!(
+            incoming,
+            Incoming::Event(ServerEvent::NickChange { new_nick, .. }) if new_nick == nick
+        )
+    })
+}
+
+fn received_chat(events: &mpsc::Receiver<Incoming>, nick: &str, text: &str) -> bool {
+    events.try_iter().any(|incoming| {
+        matches!(
+            incoming,
+            Incoming::Event(ServerEvent::Chat { nick: from, text: said, .. })
+                if from == nick && said == text
+        )
+    })
+}
+
+// Connects a client and waits until the server knows it as `nick`.
+async fn connect(url: &str, nick: &str) -> (ChatClient, mpsc::Receiver<Incoming>) {
+    let client = ChatClient::connect(ConnectOptions::new(url), nick, Outbox::new());
+    let events = client.subscribe();
+    wait_until(|| renamed_to(&events, nick)).await;
+    (client, events)
+}
+
+#[tokio::test]
+async fn bots_can_talk_to_each_other() {
+    let url = start_server().await;
+    let (echo, echo_events) = connect(&url, "echo").await;
+    let (alice, alice_events) = connect(&url, "alice").await;
+    alice.send("ping");
+
+    // The echo bot answers every message from someone else.
+    wait_until(|| {
+        while let Ok(incoming) = echo_events.try_recv() {
+            if let Incoming::Event(ServerEvent::Chat { nick, text, .. }) = incoming
+                && nick != "echo"
+            {
+                echo.send(&format!("{} said {}", nick, text));
+            }
+        }
+        echo.pending().is_empty() && received_chat(&alice_events, "echo", "alice said ping")
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn refused_nick_change_is_rolled_back() {
+    let url = start_server().await;
+    let (_alice, _) = connect(&url, "alice").await;
+    let (bob, _) = connect(&url, "bob").await;
+
+    bob.set_nick("alice");
+    assert_eq!(bob.nick(), "alice");
+    wait_until(|| bob.nick() == "bob").await;
+}
+
+#[tokio::test]
+async fn messages_after_a_refused_nick_are_delivered() {
+    let url = start_server().await;
+    let (_alice, alice_events) = connect(&url, "alice").await;
+    let (bob, _) = connect(&url, "bob").await;
+
+    bob.set_nick("alice");
+    bob.send("important message");
+    wait_until(|| received_chat(&alice_events, "bob", "important message")).await;
+}
+
+#[tokio::test]
+async fn dropping_a_client_disconnects_it() {
+    let url = start_server().await;
+    let (_alice, alice_events) = connect(&url, "alice").await;
+    let (bot, _) = connect(&url, "bot").await;
+
+    drop(bot);
+    wait_until(|| {
+        alice_events.try_iter().any(|incoming| {
+            matches!(
+                incoming,
+                Incoming::Event(ServerEvent::Leave { nick, .. }) if nick == "bot"
+            )
+        })
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn messages_queued_offline_follow_the_rejoin() {
+    let addr = free_addr();
+    let server = serve(addr);
+    let (alice, events) = connect(&format!("ws://{}", addr), "alice").await;
+    alice.join("rust");
+    wait_until(|| {
+        events.try_iter().any(|incoming| {
+            matches!(
+                incoming,
+                Incoming::Event(ServerEvent::Join { room, nick, .. })
+                    if room == "rust" && nick == "alice"
+            )
+        })
+    })
+    .await;
+
+    server.shutdown_background();
+    wait_until(|| alice.status().state != ConnectionState::Connected).await;
+    alice.send_to("rust", "queued while offline");
+    let server = serve(addr);
+
+    let mut replayed = None;
+    wait_until(|| {
+        for incoming in events.try_iter() {
+            if let Incoming::Event(ServerEvent::Chat {
+                room, nick, text, ..
+            }) = incoming
+                && text == "queued while offline"
+            {
+                replayed = Some((room, nick));
+            }
+        }
+        replayed.is_some()
+    })
+    .await;
+    assert_eq!(replayed, Some(("rust".to_string(), "alice".to_string())));
+    server.shutdown_background();
+}
+
+#[tokio::test]
+async fn messages_queued_before_connecting_use_the_servers_protocol() {
+    let addr = free_addr();
+    let alice = ChatClient::connect(
+        ConnectOptions::new(format!("ws://{}", addr)),
+        "alice",
+        Outbox::new(),
+    );
+    let events = alice.subscribe();
+    // Nothing is known about the server yet; a legacy frame would arrive
+    // as the text "* waves".
+    alice.send_action("general", "waves");
+    let server = serve(addr);
+
+    let mut action = None;
+    wait_until(|| {
+        for incoming in events.try_iter() {
+            if let Incoming::Event(ServerEvent::Chat {
+                nick,
+                text,
+                action: is_action,
+                ..
+            }) = incoming
+                && nick == "alice"
+            {
+                action = Some((text, is_action));
+            }
+        }
+        action.is_some()
+    })
+    .await;
+    assert_eq!(action, Some(("waves".to_string(), true)));
+    server.shutdown_background();
+}
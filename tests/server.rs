@@ -0,0 +1,581 @@
+use std::{sync::mpsc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use termchat::{
+    client::ChatClient,
+    connection::ConnectOptions,
+    outbox::Outbox,
+    protocol::{ClientMessage, Incoming, PROTOCOL_VERSION, Presence, ServerEvent},
+    server::Server,
+    store::MessageStore,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
+
+async fn start_server() -> String {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    tokio::spawn(server.run());
+    url
+}
+
+async fn start_server_with_store(replay: usize) -> String {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_store(MessageStore::in_memory().unwrap(), replay);
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    tokio::spawn(server.run());
+    url
+}
+
+fn event(frame: &str) -> ServerEvent {
+    match Incoming::parse(frame) {
+        Incoming::Event(event) => event,
+        Incoming::Legacy(text) => panic!("expected a structured frame, got {text}"),
+    }
+}
+
+async fn recv(events: &mpsc::Receiver<Incoming>) -> ServerEvent {
+    for _ in 0..500 {
+        if let Ok(incoming) = events.try_recv() {
+            match incoming {
+                Incoming::Event(event) => return event,
+                Incoming::Legacy(text) => panic!("expected a structured frame, got {text}"),
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no message from server");
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn next_event(socket: &mut Socket) -> ServerEvent {
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("no message from server")
+        .unwrap()
+        .unwrap();
+    event(message.to_text().unwrap())
+}
+
+// Connects and consumes the greeting: welcome, own join notice and roster.
+async fn connect(url: &str) -> Socket {
+    let (mut socket, _) = connect_async(url).await.unwrap();
+    for _ in 0..3 {
+        next_event(&mut socket).await;
+    }
+    socket
+}
+
+#[tokio::test]
+async fn client_sees_join_nick_change_and_chat() {
+    let url = start_server().await;
+
+    let client = ChatClient::connect(ConnectOptions::new(url), "alice", Outbox::new());
+    let events = client.subscribe();
+
+    assert_eq!(
+        recv(&events).await,
+        ServerEvent::Welcome {
+            version: PROTOCOL_VERSION,
+            nick: "127.0.0.1".to_string()
+        }
+    );
+    assert!(matches!(recv(&events).await, ServerEvent::Join { nick, .. } if nick == "127.0.0.1"));
+    assert!(matches!(
+        recv(&events).await,
+        ServerEvent::Roster { room, members } if room == "general" && members.len() == 1
+    ));
+
+    client.send("hi\nthere");
+
+    assert!(matches!(
+        recv(&events).await,
+        ServerEvent::NickChange { old_nick, new_nick, .. }
+            if old_nick == "127.0.0.1" && new_nick == "alice"
+    ));
+    assert!(matches!(
+        recv(&events).await,
+        ServerEvent::Chat { nick, text, .. } if nick == "alice" && text == "hi\nthere"
+    ));
+}
+
+#[tokio::test]
+async fn others_are_told_when_a_client_leaves() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    alice
+        .send(Message::Text(r#"{"nick":"alice","message":"bye"}"#.into()))
+        .await
+        .unwrap();
+    // Nick change and the message itself.
+    for _ in 0..2 {
+        next_event(&mut alice).await;
+    }
+
+    let (mut bob, _) = connect_async(url.as_str()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Welcome { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Join { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Roster { members, .. }
+            if members.iter().any(|member| member.nick == "alice")
+    ));
+
+    alice.close(None).await.unwrap();
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Leave { nick, .. } if nick == "alice"
+    ));
+}
+
+async fn send_message(socket: &mut Socket, message: ClientMessage) {
+    socket
+        .send(Message::Text(message.to_frame().into()))
+        .await
+        .unwrap();
+}
+
+// Renames a client and consumes the notice.
+async fn rename(socket: &mut Socket, nick: &str) {
+    let message = ClientMessage::Nick {
+        nick: nick.to_string(),
+    };
+    send_message(socket, message).await;
+    assert!(matches!(
+        next_event(socket).await,
+        ServerEvent::NickChange { new_nick, .. } if new_nick == nick
+    ));
+}
+
+fn chat(nick: &str, room: &str, text: &str) -> ClientMessage {
+    ClientMessage::Chat {
+        nick: nick.to_string(),
+        room: room.to_string(),
+        text: text.to_string(),
+        action: false,
+    }
+}
+
+#[tokio::test]
+async fn chat_only_reaches_members_of_the_room() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    send_message(
+        &mut alice,
+        ClientMessage::Join {
+            room: "rust".to_string(),
+        },
+    )
+    .await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Join { room, .. } if room == "rust"
+    ));
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Roster { room, .. } if room == "rust"
+    ));
+
+    let mut bob = connect(&url).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Join { room, .. } if room == "general"
+    ));
+
+    send_message(&mut alice, chat("alice", "rust", "rust only")).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Chat { room, text, .. } if room == "rust" && text == "rust only"
+    ));
+
+    send_message(&mut bob, chat("bob", "general", "hello")).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Chat { room, text, .. } if room == "general" && text == "hello"
+    ));
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_the_recipient() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    rename(&mut alice, "alice").await;
+
+    let mut bob = connect(&url).await;
+    rename(&mut bob, "bob").await;
+    let mut carol = connect(&url).await;
+    rename(&mut carol, "carol").await;
+
+    let direct = ClientMessage::Direct {
+        nick: "bob".to_string(),
+        to: "alice".to_string(),
+        text: "psst".to_string(),
+    };
+    send_message(&mut bob, direct).await;
+
+    for socket in [&mut alice, &mut bob] {
+        loop {
+            match next_event(socket).await {
+                ServerEvent::Direct { from, to, text, .. } => {
+                    assert_eq!((from.as_str(), to.as_str()), ("bob", "alice"));
+                    assert_eq!(text, "psst");
+                    break;
+                }
+                ServerEvent::Join { .. } | ServerEvent::NickChange { .. } => {}
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+    }
+
+    // Carol only sees renames, then her own message.
+    send_message(&mut carol, chat("carol", "general", "anyone?")).await;
+    loop {
+        match next_event(&mut carol).await {
+            ServerEvent::NickChange { .. } => {}
+            ServerEvent::Chat { nick, .. } => {
+                assert_eq!(nick, "carol");
+                break;
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn direct_message_to_offline_nick_is_an_error() {
+    let url = start_server().await;
+
+    let mut socket = connect(&url).await;
+    let direct = ClientMessage::Direct {
+        nick: "bob".to_string(),
+        to: "nobody".to_string(),
+        text: "hello?".to_string(),
+    };
+    send_message(&mut socket, direct).await;
+
+    assert!(matches!(
+        next_event(&mut socket).await,
+        ServerEvent::Error { message } if message == "nobody is not online"
+    ));
+}
+
+#[tokio::test]
+async fn empty_nick_is_rejected_with_error() {
+    let url = start_server().await;
+
+    let mut socket = connect(&url).await;
+    socket
+        .send(Message::Text(r#"{"nick":" ","message":"hi"}"#.into()))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        next_event(&mut socket).await,
+        ServerEvent::NickRejected { .. }
+    ));
+}
+
+#[tokio::test]
+async fn taken_nick_is_rejected() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    let nick = |nick: &str| ClientMessage::Nick {
+        nick: nick.to_string(),
+    };
+    send_message(&mut alice, nick("alice")).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::NickChange { new_nick, .. } if new_nick == "alice"
+    ));
+
+    let mut bob = connect(&url).await;
+    send_message(&mut bob, nick("alice")).await;
+    assert_eq!(
+        next_event(&mut bob).await,
+        ServerEvent::NickRejected {
+            nick: "alice".to_string(),
+            reason: "Nickname is already taken".to_string(),
+        }
+    );
+}
+
+#[tokio::test]
+async fn presence_changes_reach_everyone_and_the_roster() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    let mut bob = connect(&url).await;
+    next_event(&mut alice).await;
+
+    send_message(
+        &mut alice,
+        ClientMessage::Presence {
+            presence: Presence::Away,
+        },
+    )
+    .await;
+    for socket in [&mut alice, &mut bob] {
+        assert!(matches!(
+            next_event(socket).await,
+            ServerEvent::PresenceChange {
+                presence: Presence::Away,
+                ..
+            }
+        ));
+    }
+
+    let (mut carol, _) = connect_async(url.as_str()).await.unwrap();
+    for _ in 0..2 {
+        next_event(&mut carol).await;
+    }
+    let ServerEvent::Roster { members, .. } = next_event(&mut carol).await else {
+        panic!("expected the roster");
+    };
+    let presences: Vec<Presence> = members.iter().map(|member| member.presence).collect();
+    assert_eq!(presences.len(), 3);
+    assert_eq!(
+        presences
+            .iter()
+            .filter(|presence| **presence == Presence::Away)
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn typing_is_relayed_to_the_rest_of_the_room() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    let mut bob = connect(&url).await;
+    next_event(&mut alice).await;
+
+    let typing = |typing: bool| ClientMessage::Typing {
+        room: "general".to_string(),
+        typing,
+    };
+    send_message(&mut alice, typing(true)).await;
+    send_message(&mut alice, typing(false)).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Typing { typing: true, .. }
+    ));
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Typing { typing: false, .. }
+    ));
+
+    // The typist is not told about itself.
+    send_message(&mut alice, chat("alice", "general", "done")).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Chat { text, .. } if text == "done"
+    ));
+}
+
+#[tokio::test]
+async fn stored_chat_and_room_events_arrive_in_order() {
+    let url = start_server_with_store(0).await;
+
+    let mut alice = connect(&url).await;
+    let mut bob = connect(&url).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Join { .. }
+    ));
+
+    // The message is stored on its way out; leaving is not, and must not
+    // overtake it.
+    send_message(&mut bob, chat("bob", "general", "bye")).await;
+    let part = ClientMessage::Part {
+        room: "general".to_string(),
+    };
+    send_message(&mut bob, part).await;
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Chat { text, .. } if text == "bye"
+    ));
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::Leave { .. }
+    ));
+}
+
+#[tokio::test]
+async fn joining_replays_stored_messages_and_older_pages_load() {
+    let url = start_server_with_store(2).await;
+
+    let mut alice = connect(&url).await;
+    // Nothing is stored yet, so the replay is empty.
+    assert!(matches!(
+        next_event(&mut alice).await,
+        ServerEvent::History { messages, .. } if messages.is_empty()
+    ));
+    rename(&mut alice, "alice").await;
+    for text in ["one", "two", "three"] {
+        send_message(&mut alice, chat("alice", "general", text)).await;
+    }
+    for _ in 0..3 {
+        next_event(&mut alice).await;
+    }
+
+    let (mut bob, _) = connect_async(&url).await.unwrap();
+    for _ in 0..3 {
+        next_event(&mut bob).await;
+    }
+    let ServerEvent::History {
+        room,
+        messages,
+        more,
+    } = next_event(&mut bob).await
+    else {
+        panic!("expected the latest messages after joining");
+    };
+    assert_eq!(room, "general");
+    let texts: Vec<&str> = messages.iter().map(|m| m.text.as_str()).collect();
+    assert_eq!(texts, ["two", "three"]);
+    assert!(more);
+
+    send_message(
+        &mut bob,
+        ClientMessage::History {
+            room: "general".to_string(),
+            before: Some(messages[0].id),
+            limit: 10,
+        },
+    )
+    .await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::History { messages, more: false, .. }
+            if messages.len() == 1 && messages[0].text == "one" && messages[0].nick == "alice"
+    ));
+
+    send_message(
+        &mut bob,
+        ClientMessage::History {
+            room: "rust".to_string(),
+            before: None,
+            limit: 10,
+        },
+    )
+    .await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Error { .. }
+    ));
+}
+
+#[tokio::test]
+async fn chat_after_a_refused_rename_is_still_delivered() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    rename(&mut alice, "alice").await;
+    let mut bob = connect(&url).await;
+    rename(&mut bob, "bob").await;
+
+    let refused = ClientMessage::Nick {
+        nick: "alice".to_string(),
+    };
+    send_message(&mut bob, refused).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::NickRejected { .. }
+    ));
+
+    // A client that stamped the refused nick is still heard, as itself.
+    send_message(&mut bob, chat("alice", "general", "important message")).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Chat { nick, text, .. } if nick == "bob" && text == "important message"
+    ));
+    let direct = ClientMessage::Direct {
+        nick: "alice".to_string(),
+        to: "alice".to_string(),
+        text: "psst".to_string(),
+    };
+    send_message(&mut bob, direct).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Direct { from, .. } if from == "bob"
+    ));
+}
+
+#[tokio::test]
+async fn renames_only_reach_peers_sharing_a_room() {
+    let url = start_server().await;
+
+    let mut bob = connect(&url).await;
+    let part = ClientMessage::Part {
+        room: "general".to_string(),
+    };
+    send_message(&mut bob, part).await;
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Leave { .. }
+    ));
+
+    let mut alice = connect(&url).await;
+    rename(&mut alice, "alice").await;
+    // Bob's own rename is the first thing he hears of.
+    rename(&mut bob, "bob").await;
+}
+
+#[tokio::test]
+async fn legacy_chat_with_a_taken_nick_is_still_delivered() {
+    let url = start_server().await;
+
+    let mut alice = connect(&url).await;
+    rename(&mut alice, "alice").await;
+    let mut bob = connect(&url).await;
+    rename(&mut bob, "bob").await;
+
+    // Legacy clients stamp their nick on every message.
+    bob.send(Message::Text(
+        r#"{"nick":"alice","message":"important message"}"#.into(),
+    ))
+    .await
+    .unwrap();
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::NickRejected { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut bob).await,
+        ServerEvent::Chat { nick, text, .. } if nick == "bob" && text == "important message"
+    ));
+}
+
+#[tokio::test]
+async fn frames_of_another_protocol_version_are_refused() {
+    let url = start_server().await;
+
+    let mut socket = connect(&url).await;
+    let frame = ClientMessage::Join {
+        room: "rust".to_string(),
+    }
+    .to_frame()
+    .replace(r#""v":1"#, r#""v":2"#);
+    socket.send(Message::Text(frame.into())).await.unwrap();
+
+    assert_eq!(
+        next_event(&mut socket).await,
+        ServerEvent::Error {
+            message: "Unsupported protocol version 2 (this server speaks 1)".to_string(),
+        }
+    );
+}